@@ -0,0 +1,331 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, ToTokens};
+use syn::{parse, parse2, parse_str, punctuated::Punctuated, Attribute, Error, Expr, ExprLit, FnArg, Ident, Index, ItemFn, Lit, LitInt, LitStr, Meta, MetaNameValue, ReturnType, Token, Type};
+use syn::parse::{Parse, ParseStream};
+
+pub fn ktest(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function: ItemFn = match parse(item) {
+        Ok(function) => function,
+        Err(error) => return error.into_compile_error().into(),
+    };
+    let arguments: Arguments = match parse(attr) {
+        Ok(arguments) => arguments,
+        Err(error) => return error.into_compile_error().into(),
+    };
+    let name = function.sig.ident.clone();
+    let return_type = match &function.sig.output {
+        ReturnType::Default => parse_str::<Type>("()").unwrap(),
+        ReturnType::Type(_, return_type) => *return_type.clone(),
+    };
+    let attributes = match Attributes::try_from(&function.attrs) {
+        Ok(attributes) => attributes,
+        Err(error) => return error.into_compile_error().into(),
+    };
+    let ignore = attributes.ignore;
+    let ignore_reason = optional(attributes.ignore_reason);
+    let should_panic = attributes.should_panic;
+    let repeat = arguments.repeat;
+    let retries = arguments.retries;
+    let requires = arguments.requires;
+    let xfail = optional(arguments.xfail);
+    let timeout_ms = optional(arguments.timeout);
+    let is_async = function.sig.asyncness.is_some();
+
+    if return_type != parse_str::<Type>("()").unwrap()
+        && should_panic != Ident::new("No", Span::call_site())
+    {
+        return Error::new_spanned(
+            function,
+            "functions using `#[should_panic]` must return `()`",
+        )
+        .into_compile_error()
+        .into();
+    }
+
+    let fields = quote! {
+        ignore: ::ktest::test::Ignore::#ignore,
+        ignore_reason: #ignore_reason,
+        should_panic: ::ktest::test::ShouldPanic::#should_panic,
+        repeat: #repeat,
+        retries: #retries,
+        requires: &[#(#requires),*],
+        xfail: #xfail,
+        timeout_ms: #timeout_ms,
+    };
+
+    // a parameterized test is given its parameters from each case in turn
+    if let Some(cases) = arguments.cases {
+        let mut parameter_types = Vec::new();
+        for input in &function.sig.inputs {
+            match input {
+                FnArg::Typed(parameter) => parameter_types.push(*parameter.ty.clone()),
+                FnArg::Receiver(receiver) => return Error::new_spanned(receiver, "tests cannot take `self`").into_compile_error().into()
+            }
+        }
+        let (case_type, parameters) = match parameter_types.as_slice() {
+            [] => return Error::new_spanned(&function.sig, "tests with `cases` must take the case values as parameters").into_compile_error().into(),
+            [parameter_type] => (quote! { #parameter_type }, vec![quote! { case }]),
+            parameter_types => {
+                let parameters = (0..parameter_types.len())
+                    .map(|i| { let i = Index::from(i); quote! { &case.#i } })
+                    .collect();
+                (quote! { (#(#parameter_types),*) }, parameters)
+            }
+        };
+
+        let call = block_on_if(is_async, quote! { #name(#(::core::clone::Clone::clone(#parameters)),*) });
+
+        return TokenStream::from(quote! {
+            #[allow(dead_code)]
+            #function
+
+            #[test_case]
+            #[allow(non_upper_case_globals)]
+            const #name: ::ktest::test::ParamTest::<#case_type, #return_type> = ::ktest::test::ParamTest::<#case_type, #return_type> {
+                name: stringify!(#name),
+                modules: module_path!(),
+                test: {
+                    fn run_case(case: &'static #case_type) -> #return_type {
+                        #call
+                    }
+                    run_case
+                },
+                cases: &[#(#cases),*],
+                #fields
+            };
+        });
+    }
+
+    // otherwise, each parameter is a fixture, set up before the test and torn down after it
+    let mut fixture_types = Vec::new();
+    let mut parameters = Vec::new();
+    for (i, input) in function.sig.inputs.iter().enumerate() {
+        let fixture = Ident::new(&format!("fixture_{}", i), Span::call_site());
+        match input {
+            FnArg::Typed(parameter) => match &*parameter.ty {
+                // `&mut F` is the fixture `F` itself
+                Type::Reference(reference) if reference.mutability.is_some() => {
+                    let fixture_type = &reference.elem;
+                    fixture_types.push(quote! { #fixture_type });
+                    parameters.push(quote! { #fixture });
+                }
+                // `&T` is a shared fixture, e.g. `&BootInfo`, set up as a `&'static T`
+                Type::Reference(reference) => {
+                    let fixture_type = &reference.elem;
+                    fixture_types.push(quote! { &'static #fixture_type });
+                    parameters.push(quote! { *#fixture });
+                }
+                parameter_type => return Error::new_spanned(parameter_type, "fixture parameters must be references, e.g. `frames: &mut FrameArena` or `boot_info: &BootInfo`").into_compile_error().into()
+            },
+            FnArg::Receiver(receiver) => return Error::new_spanned(receiver, "tests cannot take `self`").into_compile_error().into()
+        }
+    }
+
+    let mut body = block_on_if(is_async, quote! { #name(#(#parameters),*) });
+    for (i, fixture_type) in fixture_types.iter().enumerate().rev() {
+        let fixture = Ident::new(&format!("fixture_{}", i), Span::call_site());
+        body = quote! { ::ktest::test::fixture::with::<#fixture_type, _>(|#fixture| #body) };
+    }
+    let test = if fixture_types.is_empty() && !is_async {
+        quote! { #name }
+    } else {
+        quote! {
+            {
+                fn run_test() -> #return_type {
+                    #body
+                }
+                run_test
+            }
+        }
+    };
+
+    TokenStream::from(quote! {
+        #[allow(dead_code)]
+        #function
+
+        #[test_case]
+        #[allow(non_upper_case_globals)]
+        const #name: ::ktest::test::Test::<#return_type> = ::ktest::test::Test::<#return_type> {
+            name: stringify!(#name),
+            modules: module_path!(),
+            test: #test,
+            #fields
+        };
+    })
+}
+
+/// The arguments of the `#[ktest(...)]` attribute itself, e.g. `#[ktest(repeat = 50)]`.
+struct Arguments {
+    repeat: LitInt,
+    retries: LitInt,
+    requires: Vec<LitStr>,
+    xfail: Option<LitStr>,
+    cases: Option<Vec<Expr>>,
+    timeout: Option<LitInt>
+}
+
+impl Arguments {
+    fn new() -> Self {
+        Self {
+            repeat: LitInt::new("1", Span::call_site()),
+            retries: LitInt::new("0", Span::call_site()),
+            requires: Vec::new(),
+            xfail: None,
+            cases: None,
+            timeout: None
+        }
+    }
+}
+
+impl Parse for Arguments {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut result = Arguments::new();
+
+        for argument in Punctuated::<MetaNameValue, Token![,]>::parse_terminated(input)? {
+            let Some(ident) = argument.path.get_ident() else {
+                return Err(Error::new_spanned(&argument.path, "expected an argument of the form `name = value`"));
+            };
+            match ident.to_string().as_str() {
+                "repeat" => result.repeat = int_argument(&argument)?,
+                "retries" => result.retries = int_argument(&argument)?,
+                "requires" => result.requires = strings_argument(&argument)?,
+                "xfail" => result.xfail = Some(string_argument(&argument)?),
+                "cases" => result.cases = Some(array_argument(&argument)?),
+                "timeout" => result.timeout = Some(int_argument(&argument)?),
+                _ => return Err(Error::new_spanned(ident, format!("unknown argument `{}`; expected `repeat`, `retries`, `requires`, `xfail`, `cases` or `timeout`", ident)))
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Helper function to get the integer value of an argument, e.g. `repeat = 50`
+fn int_argument(argument: &MetaNameValue) -> syn::Result<LitInt> {
+    match &argument.value {
+        Expr::Lit(ExprLit { lit: Lit::Int(int), .. }) => Ok(int.clone()),
+        value => Err(Error::new_spanned(value, "expected an integer"))
+    }
+}
+
+/// Helper function to get the string value of an argument, e.g. `xfail = "bug #123"`
+fn string_argument(argument: &MetaNameValue) -> syn::Result<LitStr> {
+    string_value(&argument.value)
+}
+
+/// Helper function to get the string values of an argument, given either as one string (e.g.
+/// `requires = "x2apic"`) or as an array (e.g. `requires = ["x2apic", "1gb-pages"]`)
+fn strings_argument(argument: &MetaNameValue) -> syn::Result<Vec<LitStr>> {
+    match &argument.value {
+        Expr::Array(array) => array.elems.iter().map(string_value).collect(),
+        value => Ok(vec![string_value(value)?])
+    }
+}
+
+/// Helper function to get the elements of an array argument, e.g. `cases = [(1, 2), (3, 4)]`
+fn array_argument(argument: &MetaNameValue) -> syn::Result<Vec<Expr>> {
+    match &argument.value {
+        Expr::Array(array) => Ok(array.elems.iter().cloned().collect()),
+        value => Err(Error::new_spanned(value, "expected an array"))
+    }
+}
+
+/// Helper function to get a string literal
+fn string_value(value: &Expr) -> syn::Result<LitStr> {
+    match value {
+        Expr::Lit(ExprLit { lit: Lit::Str(string), .. }) => Ok(string.clone()),
+        value => Err(Error::new_spanned(value, "expected a string"))
+    }
+}
+
+/// Helper function to drive the future returned by a call to an `async fn` test to completion
+fn block_on_if(is_async: bool, call: TokenStream2) -> TokenStream2 {
+    if is_async {
+        quote! { ::ktest::test::executor::block_on(#call) }
+    } else {
+        call
+    }
+}
+
+/// Helper function to write an optional value as `Some(value)` or `None`
+fn optional<T: ToTokens>(value: Option<T>) -> TokenStream2 {
+    match value {
+        Some(value) => quote! { ::core::option::Option::Some(#value) },
+        None => quote! { ::core::option::Option::None }
+    }
+}
+
+struct Attributes {
+    ignore: Ident,
+    ignore_reason: Option<LitStr>,
+    should_panic: Ident
+}
+
+impl Attributes {
+    fn new() -> Self {
+        Self {
+            ignore: Ident::new("No", Span::call_site()),
+            ignore_reason: None,
+            should_panic: Ident::new("No", Span::call_site())
+        }
+    }
+}
+
+impl TryFrom<&Vec<Attribute>> for Attributes {
+    type Error = Error;
+
+    fn try_from(attributes: &Vec<Attribute>) -> Result<Self, Self::Error> {
+        let mut result = Attributes::new();
+
+        for attribute in attributes {
+            if let Some(ident) = attribute.path().get_ident() {
+                match ident.to_string().as_str() {
+                    "ignore" => {
+                        match &attribute.meta {
+                            Meta::NameValue(name_value) => {
+                                let Expr::Lit(ExprLit { lit: Lit::Str(reason), .. }) = &name_value.value else {
+                                    return Err(Error::new_spanned(attribute, "the reason must be a string: `#[ignore = \"reason\"]`"));
+                                };
+                                result.ignore = Ident::new("YesWithMessage", Span::call_site());
+                                result.ignore_reason = Some(reason.clone());
+                            }
+                            Meta::List(_) => return Err(Error::new_spanned(attribute, "valid forms for the attribute are `#[ignore]` and `#[ignore = \"reason\"]`")),
+                            Meta::Path(_) => result.ignore = Ident::new("Yes", Span::call_site()),
+                        }
+                    }
+                    "should_panic" => {
+                        match &attribute.meta {
+                            Meta::List(meta_list) => {
+                                if let Ok(Meta::NameValue(name_value)) =
+                                    parse2(meta_list.tokens.clone())
+                                {
+                                    if name_value.path == parse_str("expected").unwrap() {
+                                        result.should_panic =
+                                            Ident::new("YesWithMessage", Span::call_site());
+                                    } else {
+                                        return Err(Error::new_spanned(attribute, "argument must be of the form: `expected = \"error message\"`"));
+                                    }
+                                } else {
+                                    return Err(Error::new_spanned(attribute, "argument must be of the form: `expected = \"error message\"`"));
+                                }
+                            }
+                            Meta::NameValue(_name_value) => {
+                                result.should_panic =
+                                    Ident::new("YesWithMessage", Span::call_site());
+                            }
+                            Meta::Path(_) => {
+                                result.should_panic = Ident::new("Yes", Span::call_site());
+                            }
+                        }
+                    }
+                    _ => {
+                        // Not supported.
+                    }
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
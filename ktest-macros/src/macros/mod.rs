@@ -0,0 +1 @@
+pub mod ktest;
\ No newline at end of file
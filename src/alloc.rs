@@ -0,0 +1,314 @@
+//! Tracking and fault injection for the heap allocator of the `allocator` feature. The global
+//! allocator counts its live allocations and bytes, so that the runner can report tests which
+//! leak heap memory, and can be made to fail allocations, so that out-of-memory paths can be
+//! tested deterministically:
+//!
+//! ```
+//! ktest::alloc::fail_after(1); // the second allocation fails
+//! let mut first = Vec::<u8>::new();
+//! let mut second = Vec::<u8>::new();
+//! ktest::assert_matches!(first.try_reserve(16), Ok(_));
+//! ktest::assert_matches!(second.try_reserve(16), Err(_));
+//! ```
+//!
+//! Only fallible allocations (such as `try_reserve`) return an error; an infallible allocation
+//! which fails (e.g. `Box::new` or `Vec::push`) goes to the allocation error handler, which fails
+//! the test.
+//!
+//! Injected faults are cleared by the runner before each test. Code which must not allocate, such
+//! as interrupt handlers, can be checked with `assert_no_alloc`, and `count_allocs` counts the
+//! allocations of a closure.
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::random::SplitMix64;
+
+/// The number of allocations which have not been freed
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of bytes in allocations which have not been freed
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The live allocations and bytes when the current test started
+static BASELINE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BASELINE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The allocations and bytes leaked by the current test, once it has finished
+static LEAKED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LEAKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// A snapshot of the heap allocations which are live (allocated, and not yet freed).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapUsage {
+    /// The number of live allocations.
+    pub allocations: usize,
+    /// The total size of the live allocations in bytes.
+    pub bytes: usize
+}
+
+/// Gets the heap allocations which are currently live. This is always empty without the
+/// `allocator` feature.
+pub fn usage() -> HeapUsage {
+    HeapUsage {
+        allocations: LIVE_ALLOCATIONS.load(Ordering::SeqCst),
+        bytes: LIVE_BYTES.load(Ordering::SeqCst)
+    }
+}
+
+/// Takes a snapshot of the live allocations, against which `finish_tracking` compares. This is
+/// called by the runner before each test.
+pub fn start_tracking() {
+    let usage = usage();
+    BASELINE_ALLOCATIONS.store(usage.allocations, Ordering::SeqCst);
+    BASELINE_BYTES.store(usage.bytes, Ordering::SeqCst);
+    LEAKED_ALLOCATIONS.store(0, Ordering::SeqCst);
+    LEAKED_BYTES.store(0, Ordering::SeqCst);
+
+    // a test which panicked inside `count_allocs` never finished counting
+    COUNTING.store(false, Ordering::SeqCst);
+    *FIRST_ALLOCATION.lock() = None;
+}
+
+/// Records the allocations which are live now, but were not when `start_tracking` was called, as
+/// leaked. This is called by the runner after a test returns; a test which panics is not checked,
+/// as its stack is never unwound.
+pub fn finish_tracking() {
+    let usage = usage();
+    LEAKED_ALLOCATIONS.store(usage.allocations.saturating_sub(BASELINE_ALLOCATIONS.load(Ordering::SeqCst)), Ordering::SeqCst);
+    LEAKED_BYTES.store(usage.bytes.saturating_sub(BASELINE_BYTES.load(Ordering::SeqCst)), Ordering::SeqCst);
+}
+
+/// Gets the heap memory leaked by the current test, if any.
+pub fn leaked() -> Option<HeapUsage> {
+    let leaked = HeapUsage {
+        allocations: LEAKED_ALLOCATIONS.load(Ordering::SeqCst),
+        bytes: LEAKED_BYTES.load(Ordering::SeqCst)
+    };
+
+    (leaked.allocations > 0 || leaked.bytes > 0).then_some(leaked)
+}
+
+/// The number of allocations requested and their total bytes, and the number of deallocations,
+/// since startup
+static TOTAL_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_BYTES: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `count_allocs` is running, so that the first allocation is recorded
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// The first allocation requested while `count_allocs` is running
+static FIRST_ALLOCATION: Mutex<Option<Layout>> = Mutex::new(None);
+
+/// The heap allocations made by a closure, as counted by `count_allocs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    /// The number of allocations requested, including those which failed.
+    pub allocations: usize,
+    /// The total size of the requested allocations in bytes.
+    pub bytes: usize,
+    /// The number of deallocations.
+    pub deallocations: usize,
+    /// The layout of the first allocation requested, if any.
+    pub first: Option<Layout>
+}
+
+/// Runs the given closure, and counts the heap allocations it makes:
+///
+/// ```
+/// let stats = ktest::count_allocs(|| { let _ = Box::new(42u64); });
+/// ktest::assert_eq!(stats.allocations, 1);
+/// ```
+///
+/// Calls can be nested; the allocations of the inner closure are also counted by the outer one.
+/// The stats are always empty without the `allocator` feature.
+pub fn count_allocs<F: FnOnce()>(f: F) -> AllocStats {
+    let outer_first = FIRST_ALLOCATION.lock().take();
+    let outer_counting = COUNTING.swap(true, Ordering::SeqCst);
+    let (allocations, bytes, deallocations) = (
+        TOTAL_ALLOCATIONS.load(Ordering::SeqCst),
+        TOTAL_BYTES.load(Ordering::SeqCst),
+        TOTAL_DEALLOCATIONS.load(Ordering::SeqCst)
+    );
+
+    f();
+
+    let stats = AllocStats {
+        allocations: TOTAL_ALLOCATIONS.load(Ordering::SeqCst) - allocations,
+        bytes: TOTAL_BYTES.load(Ordering::SeqCst) - bytes,
+        deallocations: TOTAL_DEALLOCATIONS.load(Ordering::SeqCst) - deallocations,
+        first: FIRST_ALLOCATION.lock().take()
+    };
+
+    // the outer closure made the first allocation, unless it had not allocated before this one
+    COUNTING.store(outer_counting, Ordering::SeqCst);
+    *FIRST_ALLOCATION.lock() = outer_first.or(stats.first);
+    stats
+}
+
+/// Runs the given closure, and fails the current test if it allocates on the heap. The failure
+/// names the size of the first allocation and the location of this call:
+///
+/// ```
+/// let handled = ktest::assert_no_alloc(|| handle_timer_interrupt(&mut state));
+/// ```
+///
+/// This never fails without the `allocator` feature.
+#[track_caller]
+pub fn assert_no_alloc<R, F: FnOnce() -> R>(f: F) -> R {
+    let mut result = None;
+    let stats = count_allocs(|| result = Some(f()));
+
+    if let Some(first) = stats.first {
+        panic!(
+            "assertion `assert_no_alloc` failed: allocated {} bytes (alignment {}); {} allocations of {} bytes in total",
+            first.size(), first.align(), stats.allocations, stats.bytes
+        );
+    }
+    result.unwrap()
+}
+
+/// Whether any heap faults are injected; checked before locking `HEAP_FAULTS`
+static HEAP_FAULTS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The faults injected into the heap allocator
+static HEAP_FAULTS: Mutex<FaultInjector> = Mutex::new(FaultInjector::new());
+
+/// Makes heap allocation fail after `allocations` more successful allocations, until the faults
+/// are cleared. Has no effect without the `allocator` feature.
+pub fn fail_after(allocations: usize) {
+    HEAP_FAULTS.lock().fail_after(allocations);
+    HEAP_FAULTS_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Makes each heap allocation fail with the given probability (from `0.0` to `1.0`), until the
+/// faults are cleared. The failures are drawn from a generator seeded with `seed`, so the same
+/// seed fails the same allocations. Has no effect without the `allocator` feature.
+pub fn fail_probability(probability: f64, seed: u64) {
+    HEAP_FAULTS.lock().fail_probability(probability, seed);
+    HEAP_FAULTS_ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Stops failing heap allocations. This is called by the runner before each test.
+pub fn clear_faults() {
+    HEAP_FAULTS_ENABLED.store(false, Ordering::SeqCst);
+    HEAP_FAULTS.lock().clear();
+}
+
+/// Decides whether allocations should fail, to test out-of-memory handling. This is used for the
+/// heap allocator and the frame allocator.
+pub struct FaultInjector {
+    fault: Fault,
+    rng: SplitMix64
+}
+
+enum Fault {
+    None,
+    /// Fail once this many more allocations have succeeded
+    After(usize),
+    /// Fail when the next random number is below this threshold
+    Probability(u64)
+}
+
+impl FaultInjector {
+    /// Creates a fault injector which never fails.
+    pub const fn new() -> Self {
+        FaultInjector {
+            fault: Fault::None,
+            rng: SplitMix64::new(0)
+        }
+    }
+
+    /// Fails every allocation after `allocations` more successful allocations.
+    pub fn fail_after(&mut self, allocations: usize) {
+        self.fault = Fault::After(allocations);
+    }
+
+    /// Fails each allocation with the given probability (from `0.0` to `1.0`), drawn from a
+    /// generator seeded with `seed`.
+    pub fn fail_probability(&mut self, probability: f64, seed: u64) {
+        let threshold = (probability.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+        self.fault = Fault::Probability(threshold);
+        self.rng = SplitMix64::new(seed);
+    }
+
+    /// Stops failing allocations.
+    pub fn clear(&mut self) {
+        self.fault = Fault::None;
+    }
+
+    /// Whether the next allocation should fail.
+    pub fn should_fail(&mut self) -> bool {
+        match &mut self.fault {
+            Fault::None => false,
+            Fault::After(0) => true,
+            Fault::After(remaining) => {
+                *remaining -= 1;
+                false
+            }
+            Fault::Probability(threshold) => {
+                let threshold = *threshold;
+                self.rng.next_u64() < threshold
+            }
+        }
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A global allocator which wraps a `LockedHeap`, counting its allocations and live bytes (for leak
+/// detection and `count_allocs`), and failing allocations as set by `fail_after` and
+/// `fail_probability`.
+#[cfg(feature = "allocator")]
+pub struct TrackingHeap {
+    heap: linked_list_allocator::LockedHeap
+}
+
+#[cfg(feature = "allocator")]
+impl TrackingHeap {
+    /// Creates an empty heap; it must be initialized before anything is allocated.
+    pub const fn empty() -> Self {
+        TrackingHeap {
+            heap: linked_list_allocator::LockedHeap::empty()
+        }
+    }
+
+    /// The wrapped heap, e.g. to initialize or extend it.
+    pub fn heap(&self) -> &linked_list_allocator::LockedHeap {
+        &self.heap
+    }
+}
+
+#[cfg(feature = "allocator")]
+unsafe impl core::alloc::GlobalAlloc for TrackingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        TOTAL_ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        TOTAL_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+        if COUNTING.load(Ordering::SeqCst) {
+            FIRST_ALLOCATION.lock().get_or_insert(layout);
+        }
+
+        if HEAP_FAULTS_ENABLED.load(Ordering::SeqCst) && HEAP_FAULTS.lock().should_fail() {
+            return core::ptr::null_mut();
+        }
+
+        let ptr = unsafe { self.heap.alloc(layout) };
+        if !ptr.is_null() {
+            LIVE_ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.heap.dealloc(ptr, layout) };
+        TOTAL_DEALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        LIVE_ALLOCATIONS.fetch_sub(1, Ordering::SeqCst);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
@@ -1,10 +1,24 @@
 use conquer_once::spin::OnceCell;
 use heapless::String;
-use crate::MAX_STRING_LENGTH;
+use crate::{KlibConfig, MAX_STRING_LENGTH};
 
 /// A global variable to hold the test group name (only one test group per binary)
 static TEST_GROUP: OnceCell<String<MAX_STRING_LENGTH>> = OnceCell::uninit();
 
+/// A global variable to hold the runner configuration (only one configuration per binary)
+static KLIB_CONFIG: OnceCell<&'static KlibConfig> = OnceCell::uninit();
+
+/// A global variable to hold the boot information given to the kernel, for fixtures
+#[cfg(not(feature = "limine"))]
+static BOOT_INFO: OnceCell<&'static bootloader_api::BootInfo> = OnceCell::uninit();
+
+/// A global variable to hold the boot information collected from Limine, for fixtures
+#[cfg(feature = "limine")]
+static BOOT_INFO: OnceCell<crate::LimineBootInfo> = OnceCell::uninit();
+
+/// The configuration used when none has been set.
+static DEFAULT_KLIB_CONFIG: KlibConfig = KlibConfig::new_default();
+
 /// Sets the test group name. This should be called once during test initialization.
 pub fn set_test_group(name: &str) {
     TEST_GROUP.get_or_init(|| name.try_into().unwrap());
@@ -14,3 +28,37 @@ pub fn set_test_group(name: &str) {
 pub fn get_test_group() -> Option<&'static str> {
     TEST_GROUP.get().map(|s| s.as_str())
 }
+
+/// Sets the runner configuration. This should be called once during test initialization.
+pub fn set_klib_config(config: &'static KlibConfig) {
+    KLIB_CONFIG.get_or_init(|| config);
+}
+
+/// Gets the runner configuration, or the default configuration if none was set.
+pub fn get_klib_config() -> &'static KlibConfig {
+    KLIB_CONFIG.get().copied().unwrap_or(&DEFAULT_KLIB_CONFIG)
+}
+
+/// Sets the boot information. This should be called once during test initialization.
+#[cfg(not(feature = "limine"))]
+pub fn set_boot_info(boot_info: &'static bootloader_api::BootInfo) {
+    BOOT_INFO.get_or_init(|| boot_info);
+}
+
+/// Gets the boot information, if set.
+#[cfg(not(feature = "limine"))]
+pub fn get_boot_info() -> Option<&'static bootloader_api::BootInfo> {
+    BOOT_INFO.get().copied()
+}
+
+/// Sets the boot information, returning the stored value. Only the first call has any effect.
+#[cfg(feature = "limine")]
+pub fn set_boot_info(boot_info: crate::LimineBootInfo) -> &'static crate::LimineBootInfo {
+    BOOT_INFO.get_or_init(|| boot_info)
+}
+
+/// Gets the boot information, if set.
+#[cfg(feature = "limine")]
+pub fn get_boot_info() -> Option<&'static crate::LimineBootInfo> {
+    BOOT_INFO.get()
+}
@@ -0,0 +1,170 @@
+use core::arch::x86_64::{CpuidResult, __cpuid, __cpuid_count};
+use conquer_once::spin::OnceCell;
+
+/// A CPUID register holding feature bits.
+#[derive(Clone, Copy)]
+enum Register {
+    Ebx,
+    Ecx,
+    Edx
+}
+
+/// CPU features which tests can require, as `(name, leaf, subleaf, register, bit)`.
+const FEATURES: &[(&str, u32, u32, Register, u32)] = &[
+    // leaf 0x1
+    ("sse3", 0x1, 0, Register::Ecx, 0),
+    ("pclmulqdq", 0x1, 0, Register::Ecx, 1),
+    ("ssse3", 0x1, 0, Register::Ecx, 9),
+    ("fma", 0x1, 0, Register::Ecx, 12),
+    ("cx16", 0x1, 0, Register::Ecx, 13),
+    ("pcid", 0x1, 0, Register::Ecx, 17),
+    ("sse4.1", 0x1, 0, Register::Ecx, 19),
+    ("sse4.2", 0x1, 0, Register::Ecx, 20),
+    ("x2apic", 0x1, 0, Register::Ecx, 21),
+    ("movbe", 0x1, 0, Register::Ecx, 22),
+    ("popcnt", 0x1, 0, Register::Ecx, 23),
+    ("tsc-deadline", 0x1, 0, Register::Ecx, 24),
+    ("aes", 0x1, 0, Register::Ecx, 25),
+    ("xsave", 0x1, 0, Register::Ecx, 26),
+    ("avx", 0x1, 0, Register::Ecx, 28),
+    ("f16c", 0x1, 0, Register::Ecx, 29),
+    ("rdrand", 0x1, 0, Register::Ecx, 30),
+    ("hypervisor", 0x1, 0, Register::Ecx, 31),
+    ("fpu", 0x1, 0, Register::Edx, 0),
+    ("pse", 0x1, 0, Register::Edx, 3),
+    ("tsc", 0x1, 0, Register::Edx, 4),
+    ("msr", 0x1, 0, Register::Edx, 5),
+    ("pae", 0x1, 0, Register::Edx, 6),
+    ("apic", 0x1, 0, Register::Edx, 9),
+    ("pge", 0x1, 0, Register::Edx, 13),
+    ("pat", 0x1, 0, Register::Edx, 16),
+    ("sse", 0x1, 0, Register::Edx, 25),
+    ("sse2", 0x1, 0, Register::Edx, 26),
+    // leaf 0x7, subleaf 0
+    ("fsgsbase", 0x7, 0, Register::Ebx, 0),
+    ("bmi1", 0x7, 0, Register::Ebx, 3),
+    ("avx2", 0x7, 0, Register::Ebx, 5),
+    ("smep", 0x7, 0, Register::Ebx, 7),
+    ("bmi2", 0x7, 0, Register::Ebx, 8),
+    ("invpcid", 0x7, 0, Register::Ebx, 10),
+    ("avx512f", 0x7, 0, Register::Ebx, 16),
+    ("rdseed", 0x7, 0, Register::Ebx, 18),
+    ("smap", 0x7, 0, Register::Ebx, 20),
+    ("umip", 0x7, 0, Register::Ecx, 2),
+    ("pku", 0x7, 0, Register::Ecx, 3),
+    ("la57", 0x7, 0, Register::Ecx, 16),
+    // leaf 0x8000_0001
+    ("syscall", 0x8000_0001, 0, Register::Edx, 11),
+    ("nx", 0x8000_0001, 0, Register::Edx, 20),
+    ("1gb-pages", 0x8000_0001, 0, Register::Edx, 26),
+    ("rdtscp", 0x8000_0001, 0, Register::Edx, 27)
+];
+
+/// Alternate names for features, as `(alias, name)`.
+const ALIASES: &[(&str, &str)] = &[
+    ("pdpe1gb", "1gb-pages"),
+    ("page1gb", "1gb-pages"),
+    ("sse4_1", "sse4.1"),
+    ("sse4_2", "sse4.2"),
+    ("tsc_deadline", "tsc-deadline"),
+    ("xd", "nx")
+];
+
+/// Checks whether the CPU supports the named feature (e.g. `"x2apic"` or `"1gb-pages"`), using
+/// CPUID. Names are case-insensitive. Returns `None` if the name is not a known feature.
+pub fn has_feature(name: &str) -> Option<bool> {
+    let name = ALIASES.iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+        .map_or(name, |(_, name)| name);
+    let &(_, leaf, subleaf, register, bit) = FEATURES.iter()
+        .find(|(feature, ..)| feature.eq_ignore_ascii_case(name))?;
+
+    // leaves above the maximum supported leaf return garbage, so check the range first
+    let max_leaf = __cpuid(leaf & 0x8000_0000).eax;
+    if leaf > max_leaf {
+        return Some(false);
+    }
+
+    let CpuidResult { ebx, ecx, edx, .. } = __cpuid_count(leaf, subleaf);
+    let value = match register {
+        Register::Ebx => ebx,
+        Register::Ecx => ecx,
+        Register::Edx => edx
+    };
+
+    Some(value & (1 << bit) != 0)
+}
+
+/// Finds the first of the named features which the CPU does not support. Unknown feature names
+/// are treated as missing, so a typo never lets a test run on the wrong CPU.
+pub fn missing_feature(names: &[&'static str]) -> Option<&'static str> {
+    names.iter()
+        .copied()
+        .find(|name| has_feature(name) != Some(true))
+}
+
+/// Gets the frequency of the time stamp counter in cycles per second. This is read from CPUID
+/// when the CPU reports it, and otherwise measured once against the PIT.
+pub fn tsc_frequency() -> u64 {
+    static TSC_FREQUENCY: OnceCell<u64> = OnceCell::uninit();
+
+    *TSC_FREQUENCY.get_or_init(|| cpuid_tsc_frequency().unwrap_or_else(calibrate_tsc_frequency))
+}
+
+/// Reads the TSC frequency from CPUID leaf 0x15 (or the base frequency in leaf 0x16).
+fn cpuid_tsc_frequency() -> Option<u64> {
+    let max_leaf = __cpuid(0).eax;
+
+    if max_leaf >= 0x15 {
+        let CpuidResult { eax: denominator, ebx: numerator, ecx: crystal_hz, .. } = __cpuid(0x15);
+        if denominator != 0 && numerator != 0 && crystal_hz != 0 {
+            return Some(crystal_hz as u64 * numerator as u64 / denominator as u64);
+        }
+    }
+
+    if max_leaf >= 0x16 {
+        let base_mhz = __cpuid(0x16).eax & 0xffff;
+        if base_mhz != 0 {
+            return Some(base_mhz as u64 * 1_000_000);
+        }
+    }
+
+    None
+}
+
+/// Measures the TSC frequency by counting cycles during a 10 ms one-shot of PIT channel 2.
+fn calibrate_tsc_frequency() -> u64 {
+    use x86_64::instructions::port::Port;
+
+    const PIT_FREQUENCY: u64 = 1_193_182;
+    const CALIBRATION_MS: u64 = 10;
+    const PIT_COUNT: u16 = (PIT_FREQUENCY * CALIBRATION_MS / 1000) as u16;
+    const FALLBACK_FREQUENCY: u64 = 1_000_000_000; // used if the PIT never fires
+
+    let mut gate: Port<u8> = Port::new(0x61);
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel_2: Port<u8> = Port::new(0x42);
+
+    unsafe {
+        // enable the channel 2 gate, with the speaker disconnected
+        let value = gate.read();
+        gate.write((value & !0x02) | 0x01);
+
+        // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+        command.write(0xb0);
+        channel_2.write(PIT_COUNT as u8);
+        channel_2.write((PIT_COUNT >> 8) as u8);
+    }
+
+    let start = unsafe { core::arch::x86_64::_rdtsc() };
+    let mut polls = 0u64;
+    while unsafe { gate.read() } & 0x20 == 0 { // bit 5 is the channel 2 output
+        polls += 1;
+        if polls > 100_000_000 {
+            return FALLBACK_FREQUENCY;
+        }
+    }
+    let end = unsafe { core::arch::x86_64::_rdtsc() };
+
+    (end - start) * (1000 / CALIBRATION_MS)
+}
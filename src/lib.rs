@@ -1,6 +1,7 @@
 #![no_std]
 
 #![feature(try_blocks)]
+#![cfg_attr(feature = "allocator", feature(alloc_error_handler))]
 
 #![cfg_attr(test, no_main)]
 #![cfg_attr(test, feature(custom_test_frameworks))]
@@ -10,8 +11,13 @@
 #[cfg(test)]
 extern crate self as ktest;
 
+pub mod alloc;
 mod args;
-mod log;
+mod cpu;
+#[cfg(feature = "limine")]
+pub mod limine;
+pub mod log;
+mod random;
 pub mod memory;
 pub mod macros;
 pub mod test;
@@ -19,8 +25,12 @@ mod qemu;
 
 /// Re-export the test runner function for use in test binaries.
 pub use ktest_macros::ktest;
+pub use alloc::{assert_no_alloc, count_allocs, AllocStats};
+#[cfg(feature = "limine")]
+pub use limine::LimineBootInfo;
+pub use log::{capture_output, CapturedOutput};
 pub use macros::klib::{KlibConfig, KlibConfigBuilder};
-pub use test::{runner::runner, Test, split_module_path, split_module_path_len};
+pub use test::{fixture::Fixture, order::Shuffle, runner::runner, Test, split_module_path, split_module_path_len};
 
 /// Maximum length for strings used in this library, to avoid dynamic allocations.
 const MAX_STRING_LENGTH: usize = 1024;
@@ -41,6 +51,59 @@ pub fn init_harness(test_group: &str) {
     args::set_test_group(test_group);
 }
 
+/// Initialize the test harness with the given test group and runner configuration. This is
+/// the same as `init_harness`, but also applies options such as `shuffle`.
+/// 
+/// For example, in your main.rs:
+/// 
+/// ```
+/// static KLIB_CONFIG: ktest::KlibConfig = ktest::KlibConfigBuilder::new_default()
+///     .shuffle()
+///     .build();
+/// 
+/// ktest::init_harness_with_config("binary", &KLIB_CONFIG);
+/// test_main();
+/// ```
+/// 
+/// If you are using the `klib!` macro, this function is called automatically.
+/// 
+pub fn init_harness_with_config(test_group: &str, config: &'static KlibConfig) {
+    args::set_test_group(test_group);
+    args::set_klib_config(config);
+}
+
+/// Give the test harness the boot information from the bootloader, so that it can be provided
+/// to tests as a fixture (e.g. `fn my_test(boot_info: &BootInfo)`). This should be called
+/// before the main test function is called.
+/// 
+/// For example, in your main.rs:
+/// 
+/// ```
+/// ktest::init_harness("binary");
+/// ktest::init_boot_info(boot_info);
+/// test_main();
+/// ```
+/// 
+/// If you are using the `klib!` macro, this function is called automatically.
+/// 
+#[cfg(not(feature = "limine"))]
+pub fn init_boot_info(boot_info: &'static bootloader_api::BootInfo) {
+    args::set_boot_info(boot_info);
+}
+
+/// Give the test harness the boot information collected from the Limine responses, so that it can
+/// be provided to tests as a fixture (e.g. `fn my_test(boot_info: &LimineBootInfo)`) and used to
+/// initialize memory. Returns the stored boot information. This should be called before the main
+/// test function is called.
+/// 
+/// If you are using the `klib!` macro, this function is called automatically, with the responses
+/// to the requests it makes.
+/// 
+#[cfg(feature = "limine")]
+pub fn init_boot_info(boot_info: LimineBootInfo) -> &'static LimineBootInfo {
+    args::set_boot_info(boot_info)
+}
+
 /// A panic handler that delegates to the test runner's panic handler. This should be
 /// included in libraries which use `ktest` to allow recovery from panics during tests.
 /// 
@@ -61,5 +124,26 @@ pub fn panic(info: &core::panic::PanicInfo) -> ! {
     use crate::test::runner::TestRunner;
     use crate::test::runner::TEST_RUNNER;
 
-    TEST_RUNNER.get().unwrap().handle_panic(info)
+    // a panic before the runner has started (e.g. while initializing memory) cannot be recovered
+    let Some(runner) = TEST_RUNNER.get() else {
+        serial_println!("\nPanic before tests started: {}", info);
+        qemu::exit(qemu::ExitCode::Failed);
+    };
+    runner.handle_panic(info)
+}
+
+/// Handles a failed heap allocation (e.g. when the test heap runs out), by failing the current test
+/// with the requested size and alignment and the heap usage, and continuing with the next test.
+/// Fallible allocations (e.g. `Vec::try_reserve`) return an error instead, and never get here.
+#[cfg(feature = "allocator")]
+#[alloc_error_handler]
+fn alloc_error(layout: core::alloc::Layout) -> ! {
+    use crate::test::runner::TestRunner;
+    use crate::test::runner::TEST_RUNNER;
+
+    let Some(runner) = TEST_RUNNER.get() else {
+        serial_println!("\nHeap allocation of {} bytes (alignment {}) failed before tests started", layout.size(), layout.align());
+        qemu::exit(qemu::ExitCode::Failed);
+    };
+    runner.handle_alloc_error(layout)
 }
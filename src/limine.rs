@@ -0,0 +1,103 @@
+use heapless::Vec;
+
+/// The maximum number of memory map entries kept in `LimineBootInfo`; further entries are dropped.
+pub const MAX_MEMORY_MAP_ENTRIES: usize = 128;
+
+/// The responses to the Limine requests made by `klib!`, collected once at startup. This is the
+/// Limine counterpart of bootloader_api's `BootInfo`: it is passed to `before_tests`, and tests
+/// can request it as a fixture (e.g. `fn my_test(boot_info: &LimineBootInfo)`).
+///
+/// Each field is `None` (or empty) if Limine did not answer the request.
+pub struct LimineBootInfo {
+    /// The physical memory map.
+    pub memory_map: Vec<MemoryMapEntry, MAX_MEMORY_MAP_ENTRIES>,
+    /// The offset of the higher-half direct map (HHDM), at which all physical memory is mapped.
+    pub hhdm_offset: Option<u64>,
+    /// The first framebuffer.
+    pub framebuffer: Option<Framebuffer>,
+    /// The physical address of the ACPI RSDP.
+    pub rsdp_address: Option<u64>,
+    /// Where the kernel executable was loaded.
+    pub kernel_address: Option<KernelAddress>,
+    /// The kernel command line.
+    pub command_line: Option<&'static str>
+}
+
+impl LimineBootInfo {
+    /// Creates an empty boot info, to be filled in from the Limine responses.
+    pub const fn new() -> Self {
+        LimineBootInfo {
+            memory_map: Vec::new(),
+            hhdm_offset: None,
+            framebuffer: None,
+            rsdp_address: None,
+            kernel_address: None,
+            command_line: None
+        }
+    }
+
+    /// The usable ranges of physical memory, from the memory map.
+    pub fn usable_regions(&self) -> impl Iterator<Item = core::ops::Range<u64>> + '_ {
+        self.memory_map.iter()
+            .filter(|entry| entry.kind == MemoryKind::Usable)
+            .map(|entry| entry.base..entry.base + entry.length)
+    }
+}
+
+impl Default for LimineBootInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An entry of the Limine memory map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryMapEntry {
+    /// The physical start address.
+    pub base: u64,
+    /// The length in bytes.
+    pub length: u64,
+    /// What the memory is used for.
+    pub kind: MemoryKind
+}
+
+/// The type of a Limine memory map entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    BootloaderReclaimable,
+    ExecutableAndModules,
+    Framebuffer
+}
+
+/// A framebuffer set up by Limine.
+#[derive(Clone, Copy, Debug)]
+pub struct Framebuffer {
+    /// The virtual address of the first pixel.
+    pub address: *mut u8,
+    /// The width in pixels.
+    pub width: u64,
+    /// The height in pixels.
+    pub height: u64,
+    /// The number of bytes per row.
+    pub pitch: u64,
+    /// The number of bits per pixel.
+    pub bpp: u16
+}
+
+// the framebuffer address is only a location; it is up to the tests to synchronize access to it
+unsafe impl Send for Framebuffer {}
+unsafe impl Sync for Framebuffer {}
+
+/// Where Limine loaded the kernel executable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KernelAddress {
+    /// The physical base address.
+    pub physical_base: u64,
+    /// The virtual base address.
+    pub virtual_base: u64
+}
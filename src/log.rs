@@ -1,12 +1,147 @@
+use core::sync::atomic::{AtomicBool, Ordering};
 use conquer_once::spin::OnceCell;
 use heapless::String;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 use uart_16550::SerialPort;
-use crate::MAX_STRING_LENGTH;
+
+/// The maximum length of the output captured from each test; later output is counted, but dropped.
+pub const MAX_CAPTURE_LENGTH: usize = 4096;
 
 /// The global serial port instance
 pub static SERIAL1: OnceCell<Mutex<SerialPort>> = OnceCell::uninit();
 
+/// Output printed through `serial_print!` and `debugcon_print!`, captured either for the current
+/// test (which the runner adds to its record) or by `capture_output`.
+pub struct CapturedOutput {
+    output: String<MAX_CAPTURE_LENGTH>,
+    dropped: usize
+}
+
+impl CapturedOutput {
+    const fn new() -> Self {
+        CapturedOutput { output: String::new(), dropped: 0 }
+    }
+
+    /// The captured output, which is empty if nothing was printed.
+    pub fn as_str(&self) -> &str {
+        self.output.as_str()
+    }
+
+    /// The captured output, or `None` if nothing was printed.
+    pub fn output(&self) -> Option<&str> {
+        (!self.output.is_empty()).then_some(self.output.as_str())
+    }
+
+    /// The number of bytes which were printed, but not captured, because the buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Forgets the captured output.
+    pub fn clear(&mut self) {
+        self.output.clear();
+        self.dropped = 0;
+    }
+}
+
+impl core::fmt::Debug for CapturedOutput {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl PartialEq<str> for CapturedOutput {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for CapturedOutput {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl core::fmt::Write for CapturedOutput {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.dropped > 0 {
+            self.dropped += s.len(); // output is never captured after a gap
+            return Ok(());
+        }
+
+        for (i, c) in s.char_indices() {
+            if self.output.push(c).is_err() {
+                self.dropped += s.len() - i;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The output captured while a test runs.
+static CAPTURE: Mutex<CapturedOutput> = Mutex::new(CapturedOutput::new());
+
+/// Whether output is currently captured. This is kept outside of `CAPTURE`, so that printing does
+/// not lock it while nothing is captured: capturing is stopped before the runner prints any
+/// results, so its own output is never captured.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// Starts capturing output from `serial_print!` and `debugcon_print!`, forgetting any earlier output.
+pub fn start_capture() {
+    CAPTURE.lock().clear();
+    CAPTURING.store(true, Ordering::SeqCst);
+}
+
+/// Stops capturing output; output printed from now on is written to its device again.
+pub fn stop_capture() {
+    CAPTURING.store(false, Ordering::SeqCst);
+}
+
+/// Locks the output captured from the current test.
+pub fn captured_output() -> MutexGuard<'static, CapturedOutput> {
+    CAPTURE.lock()
+}
+
+/// Takes the output captured from the current test, leaving it empty.
+pub fn take_captured_output() -> CapturedOutput {
+    core::mem::replace(&mut *CAPTURE.lock(), CapturedOutput::new())
+}
+
+/// Runs the given function, capturing everything it prints through `serial_print!` and
+/// `debugcon_print!` instead of writing it to the device, so that a test can assert on it:
+/// 
+/// ```
+/// let output = ktest::capture_output(|| my_logger::info!("mapped {} pages", 4));
+/// ktest::assert_eq!(output, "[info] mapped 4 pages\n");
+/// ```
+/// 
+/// The output is not added to the test's own captured output. Up to `MAX_CAPTURE_LENGTH` bytes
+/// are captured; the number of bytes beyond that is reported by `CapturedOutput::dropped`.
+pub fn capture_output<F: FnOnce()>(function: F) -> CapturedOutput {
+    // the test's own captured output is set aside, and restored afterwards
+    let was_capturing = CAPTURING.swap(true, Ordering::SeqCst);
+    let test_output = core::mem::replace(&mut *CAPTURE.lock(), CapturedOutput::new());
+
+    function();
+
+    let output = core::mem::replace(&mut *CAPTURE.lock(), test_output);
+    CAPTURING.store(was_capturing, Ordering::SeqCst);
+    output
+}
+
+/// Helper function to write to the capture buffer if capturing is active. Returns whether
+/// the output was captured.
+fn capture(args: core::fmt::Arguments) -> bool {
+    use core::fmt::Write;
+
+    if !CAPTURING.load(Ordering::SeqCst) {
+        return false;
+    }
+    let _ = CAPTURE.lock().write_fmt(args);
+    true
+}
+
 /// Initialize the global serial port
 fn init_serial() -> Mutex<SerialPort> {
     let mut serial_port = unsafe { SerialPort::new(0x3F8) };
@@ -21,6 +156,10 @@ pub fn _serial_print(args: core::fmt::Arguments) {
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
+        if capture(args) {
+            return;
+        }
+
         let serial = SERIAL1.get_or_init(|| init_serial());
         serial.lock()
             .write_fmt(args)
@@ -31,51 +170,64 @@ pub fn _serial_print(args: core::fmt::Arguments) {
 /// Print to the global serial port
 #[macro_export]
 macro_rules! serial_print {
-    ($($arg:tt)*) => {
+    ($($arg:tt)*) => {{
         $crate::log::_serial_print(format_args!($($arg)*));
-    };
+    }};
 }
 
 /// Print to the global serial port, with a newline
 #[macro_export]
 macro_rules! serial_println {
-    ($($arg:tt)*) => {
+    ($($arg:tt)*) => {{
         $crate::log::_serial_print(format_args!($($arg)*));
         $crate::log::_serial_print(format_args!("\n"));
-    };
+    }};
 }
 
 /// Print to the debug console (macro helper)
 #[doc(hidden)]
 pub fn _debugcon_print(args: core::fmt::Arguments) {
-    // convert args to string
     use core::fmt::Write;
-    let mut s = String::<MAX_STRING_LENGTH>::new();
-    s.write_fmt(args).expect("Failed to write to string");
-
-    // this is unsafe because we are calling assembly code
-    // in this case, writing to the debug console port (0xe9)
-    // only a single byte at a time is written
-    unsafe {
-        for byte in s.bytes() {
-            core::arch::asm!("out 0xe9, al", in("al") byte);
+    use x86_64::instructions::interrupts;
+
+    if interrupts::without_interrupts(|| capture(args)) {
+        return;
+    }
+
+    // written straight to the port, so that records are not limited by the length of a buffer
+    let _ = DebugCon.write_fmt(args);
+}
+
+/// The debug console (port 0xe9), as a writer
+struct DebugCon;
+
+impl core::fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // this is unsafe because we are calling assembly code
+        // in this case, writing to the debug console port (0xe9)
+        // only a single byte at a time is written
+        unsafe {
+            for byte in s.bytes() {
+                core::arch::asm!("out 0xe9, al", in("al") byte);
+            }
         }
+        Ok(())
     }
 }
 
 /// Print to the debug console
 #[macro_export]
 macro_rules! debugcon_print {
-    ($($arg:tt)*) => {
+    ($($arg:tt)*) => {{
         $crate::log::_debugcon_print(format_args!($($arg)*));
-    };
+    }};
 }
 
 /// Print to the debug console, with a newline
 #[macro_export]
 macro_rules! debugcon_println {
-    ($($arg:tt)*) => {
+    ($($arg:tt)*) => {{
         $crate::log::_debugcon_print(format_args!($($arg)*));
         $crate::log::_debugcon_print(format_args!("\n"));
-    };
+    }};
 }
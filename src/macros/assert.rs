@@ -0,0 +1,186 @@
+//! Structured assertion macros. These behave like the `core` macros of the same names, but also
+//! record the asserted expression and the expected and actual values, which are added to the
+//! test's failure record (`"expression"`, `"expected"` and `"actual"`) and printed as an aligned
+//! diff on serial.
+
+/// `assert_eq!` function-like macro
+/// 
+/// Asserts that `actual == expected`, e.g. `ktest::assert_eq!(page.flags(), FLAGS)`.
+#[macro_export]
+macro_rules! assert_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                if !(*actual == *expected) {
+                    $crate::test::assertion::assert_failed("left == right",
+                        concat!(stringify!($actual), " == ", stringify!($expected)), expected, actual, None);
+                }
+            }
+        }
+    };
+    ($actual:expr, $expected:expr, $($arg:tt)+) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                if !(*actual == *expected) {
+                    $crate::test::assertion::assert_failed("left == right",
+                        concat!(stringify!($actual), " == ", stringify!($expected)), expected, actual, Some(format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
+
+/// `assert_ne!` function-like macro
+/// 
+/// Asserts that `actual != unexpected`, e.g. `ktest::assert_ne!(frame, previous_frame)`.
+#[macro_export]
+macro_rules! assert_ne {
+    ($actual:expr, $unexpected:expr $(,)?) => {
+        match (&$actual, &$unexpected) {
+            (actual, unexpected) => {
+                if *actual == *unexpected {
+                    $crate::test::assertion::assert_failed("left != right",
+                        concat!(stringify!($actual), " != ", stringify!($unexpected)), &format_args!("not {:?}", unexpected), actual, None);
+                }
+            }
+        }
+    };
+    ($actual:expr, $unexpected:expr, $($arg:tt)+) => {
+        match (&$actual, &$unexpected) {
+            (actual, unexpected) => {
+                if *actual == *unexpected {
+                    $crate::test::assertion::assert_failed("left != right",
+                        concat!(stringify!($actual), " != ", stringify!($unexpected)), &format_args!("not {:?}", unexpected), actual, Some(format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
+
+/// `assert_matches!` function-like macro
+/// 
+/// Asserts that a value matches a pattern, e.g. `ktest::assert_matches!(result, Err(MapToError::PageAlreadyMapped(_)))`.
+#[macro_export]
+macro_rules! assert_matches {
+    ($actual:expr, $($pattern:pat_param)|+ $(if $guard:expr)? $(,)?) => {
+        match $actual {
+            $($pattern)|+ $(if $guard)? => {}
+            ref actual => {
+                $crate::test::assertion::assert_failed("matches",
+                    concat!("matches!(", stringify!($actual), ", ", stringify!($($pattern)|+ $(if $guard)?), ")"),
+                    &format_args!("{}", stringify!($($pattern)|+ $(if $guard)?)), actual, None);
+            }
+        }
+    };
+    ($actual:expr, $($pattern:pat_param)|+ $(if $guard:expr)?, $($arg:tt)+) => {
+        match $actual {
+            $($pattern)|+ $(if $guard)? => {}
+            ref actual => {
+                $crate::test::assertion::assert_failed("matches",
+                    concat!("matches!(", stringify!($actual), ", ", stringify!($($pattern)|+ $(if $guard)?), ")"),
+                    &format_args!("{}", stringify!($($pattern)|+ $(if $guard)?)), actual, Some(format_args!($($arg)+)));
+            }
+        }
+    };
+}
+
+/// `assert_in_range!` function-like macro
+/// 
+/// Asserts that a value is contained in a range, e.g. `ktest::assert_in_range!(elapsed, 900..1100)`.
+#[macro_export]
+macro_rules! assert_in_range {
+    ($actual:expr, $range:expr $(,)?) => {
+        match (&$actual, &$range) {
+            (actual, range) => {
+                if !range.contains(actual) {
+                    $crate::test::assertion::assert_failed("range.contains(value)",
+                        concat!("(", stringify!($range), ").contains(", stringify!($actual), ")"), range, actual, None);
+                }
+            }
+        }
+    };
+    ($actual:expr, $range:expr, $($arg:tt)+) => {
+        match (&$actual, &$range) {
+            (actual, range) => {
+                if !range.contains(actual) {
+                    $crate::test::assertion::assert_failed("range.contains(value)",
+                        concat!("(", stringify!($range), ").contains(", stringify!($actual), ")"), range, actual, Some(format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
+
+/// `assert_bytes_eq!` function-like macro
+/// 
+/// Asserts that two byte buffers (anything which is `AsRef<[u8]>`) are equal, e.g.
+/// `ktest::assert_bytes_eq!(page, expected_page)`. On failure, a hexdump of both buffers around
+/// the first difference is printed on serial, and the record gains an `"offset"` field.
+#[macro_export]
+macro_rules! assert_bytes_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                let (actual, expected): (&[u8], &[u8]) = (actual.as_ref(), expected.as_ref());
+                if actual != expected {
+                    $crate::test::assertion::bytes_failed("left == right",
+                        concat!(stringify!($actual), " == ", stringify!($expected)), expected, actual, None);
+                }
+            }
+        }
+    };
+    ($actual:expr, $expected:expr, $($arg:tt)+) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                let (actual, expected): (&[u8], &[u8]) = (actual.as_ref(), expected.as_ref());
+                if actual != expected {
+                    $crate::test::assertion::bytes_failed("left == right",
+                        concat!(stringify!($actual), " == ", stringify!($expected)), expected, actual, Some(format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
+
+/// `assert_memory_eq!` function-like macro
+/// 
+/// Asserts that the memory at a pointer (or address) holds the expected bytes. On failure, a
+/// hexdump is printed as for `assert_bytes_eq!`.
+///
+/// Reading arbitrary memory is unsafe, so the macro must be used inside an `unsafe` block:
+///
+/// ```
+/// // safety: the descriptor is mapped and at least 4 bytes long
+/// unsafe { ktest::assert_memory_eq!(descriptor_ptr, [0x01, 0x00, 0x10, 0x00]) };
+/// ```
+///
+/// # Safety
+///
+/// The pointer must be valid for reads of `expected.len()` bytes.
+#[macro_export]
+macro_rules! assert_memory_eq {
+    ($pointer:expr, $expected:expr $(,)?) => {
+        match (($pointer) as *const u8, &$expected) {
+            (pointer, expected) => {
+                let expected: &[u8] = expected.as_ref();
+                let actual: &[u8] = $crate::test::assertion::read_memory(pointer, expected.len());
+                if actual != expected {
+                    $crate::test::assertion::bytes_failed("memory == expected",
+                        concat!("*", stringify!($pointer), " == ", stringify!($expected)), expected, actual, None);
+                }
+            }
+        }
+    };
+    ($pointer:expr, $expected:expr, $($arg:tt)+) => {
+        match (($pointer) as *const u8, &$expected) {
+            (pointer, expected) => {
+                let expected: &[u8] = expected.as_ref();
+                let actual: &[u8] = $crate::test::assertion::read_memory(pointer, expected.len());
+                if actual != expected {
+                    $crate::test::assertion::bytes_failed("memory == expected",
+                        concat!("*", stringify!($pointer), " == ", stringify!($expected)), expected, actual, Some(format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
@@ -0,0 +1,50 @@
+//! Soft assertion macros. These record a failure and let the test keep running, so that one
+//! test can report every mismatch in a large structure instead of only the first. The runner
+//! fails the test when it finishes if any soft assertion failed, and the failure record holds
+//! a `"failures"` array with the location and message of each one.
+
+/// `check!` function-like macro
+/// 
+/// Checks that a condition holds, recording a failure (without panicking) if it does not, e.g.
+/// `ktest::check!(entry.is_unused(), "entry {} should be unused", i)`.
+#[macro_export]
+macro_rules! check {
+    ($condition:expr $(,)?) => {
+        if !$condition {
+            $crate::test::assertion::record_soft_failure(format_args!("check failed: {}", stringify!($condition)));
+        }
+    };
+    ($condition:expr, $($arg:tt)+) => {
+        if !$condition {
+            $crate::test::assertion::record_soft_failure(format_args!("check failed: {}: {}", stringify!($condition), format_args!($($arg)+)));
+        }
+    };
+}
+
+/// `expect!` function-like macro
+/// 
+/// Checks that `actual == expected`, recording a failure with both values (without panicking)
+/// if they differ, e.g. `ktest::expect!(table.header.revision, 2)`.
+#[macro_export]
+macro_rules! expect {
+    ($actual:expr, $expected:expr $(,)?) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                if !(*actual == *expected) {
+                    $crate::test::assertion::record_soft_failure(format_args!("expected `{}` to be {:?}, but it was {:?}",
+                        stringify!($actual), expected, actual));
+                }
+            }
+        }
+    };
+    ($actual:expr, $expected:expr, $($arg:tt)+) => {
+        match (&$actual, &$expected) {
+            (actual, expected) => {
+                if !(*actual == *expected) {
+                    $crate::test::assertion::record_soft_failure(format_args!("expected `{}` to be {:?}, but it was {:?}: {}",
+                        stringify!($actual), expected, actual, format_args!($($arg)+)));
+                }
+            }
+        }
+    };
+}
@@ -32,9 +32,12 @@ macro_rules! klib {
             }
 
             fn ___kernel_test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
-                ktest::init_harness($test_group);
-                ktest::memory::heap::init_allocator_if_enabled(boot_info)
-                    .expect("Heap allocator initialization failed");
+                let boot_info: &'static bootloader_api::BootInfo = boot_info;
+                ktest::init_harness_with_config($test_group, &___KLIB_CONFIG);
+                ktest::init_boot_info(boot_info);
+                if let Err(error) = ktest::memory::init(boot_info) {
+                    panic!("Memory initialization failed: {}", error);
+                }
 
                 if let Some(before_tests) = ___KLIB_CONFIG.before_tests {
                     before_tests(boot_info);
@@ -84,6 +87,33 @@ macro_rules! klib {
             #[unsafe(link_section = ".requests_end_marker")]
             static ___END_MARKER: limine::request::RequestsEndMarker = limine::request::RequestsEndMarker::new();
 
+            // the responses are collected into a `ktest::LimineBootInfo`; the HHDM and memory map
+            // are also needed for memory management (e.g. the heap). The request types are those
+            // of the `limine` crate 0.4, which the kernel must depend on
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___HHDM_REQUEST: limine::request::HhdmRequest = limine::request::HhdmRequest::new();
+
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___MEMORY_MAP_REQUEST: limine::request::MemoryMapRequest = limine::request::MemoryMapRequest::new();
+
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___FRAMEBUFFER_REQUEST: limine::request::FramebufferRequest = limine::request::FramebufferRequest::new();
+
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___RSDP_REQUEST: limine::request::RsdpRequest = limine::request::RsdpRequest::new();
+
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___EXECUTABLE_ADDRESS_REQUEST: limine::request::ExecutableAddressRequest = limine::request::ExecutableAddressRequest::new();
+
+            #[used]
+            #[unsafe(link_section = ".requests")]
+            static ___EXECUTABLE_CMDLINE_REQUEST: limine::request::ExecutableCmdlineRequest = limine::request::ExecutableCmdlineRequest::new();
+
             static ___KLIB_CONFIG: ktest::KlibConfig = $klib_config;
 
             #[panic_handler]
@@ -91,12 +121,69 @@ macro_rules! klib {
                 ktest::panic(info)
             }
 
+            fn ___memory_kind(entry_type: limine::memory_map::EntryType) -> ktest::limine::MemoryKind {
+                use limine::memory_map::EntryType;
+                use ktest::limine::MemoryKind;
+
+                match entry_type {
+                    EntryType::USABLE => MemoryKind::Usable,
+                    EntryType::ACPI_RECLAIMABLE => MemoryKind::AcpiReclaimable,
+                    EntryType::ACPI_NVS => MemoryKind::AcpiNvs,
+                    EntryType::BAD_MEMORY => MemoryKind::BadMemory,
+                    EntryType::BOOTLOADER_RECLAIMABLE => MemoryKind::BootloaderReclaimable,
+                    EntryType::EXECUTABLE_AND_MODULES => MemoryKind::ExecutableAndModules,
+                    EntryType::FRAMEBUFFER => MemoryKind::Framebuffer,
+                    _ => MemoryKind::Reserved
+                }
+            }
+
+            fn ___collect_boot_info() -> ktest::LimineBootInfo {
+                let mut boot_info = ktest::LimineBootInfo::new();
+
+                if let Some(response) = ___MEMORY_MAP_REQUEST.get_response() {
+                    for entry in response.entries() {
+                        let entry = ktest::limine::MemoryMapEntry {
+                            base: entry.base,
+                            length: entry.length,
+                            kind: ___memory_kind(entry.entry_type)
+                        };
+                        if boot_info.memory_map.push(entry).is_err() {
+                            break; // the remaining entries are dropped
+                        }
+                    }
+                }
+                boot_info.hhdm_offset = ___HHDM_REQUEST.get_response().map(|response| response.offset());
+                boot_info.framebuffer = ___FRAMEBUFFER_REQUEST.get_response()
+                    .and_then(|response| response.framebuffers().next())
+                    .map(|framebuffer| ktest::limine::Framebuffer {
+                        address: framebuffer.addr(),
+                        width: framebuffer.width(),
+                        height: framebuffer.height(),
+                        pitch: framebuffer.pitch(),
+                        bpp: framebuffer.bpp()
+                    });
+                boot_info.rsdp_address = ___RSDP_REQUEST.get_response().map(|response| response.address() as u64);
+                boot_info.kernel_address = ___EXECUTABLE_ADDRESS_REQUEST.get_response()
+                    .map(|response| ktest::limine::KernelAddress {
+                        physical_base: response.physical_base(),
+                        virtual_base: response.virtual_base()
+                    });
+                boot_info.command_line = ___EXECUTABLE_CMDLINE_REQUEST.get_response()
+                    .and_then(|response| response.cmdline().to_str().ok());
+
+                boot_info
+            }
+
             #[unsafe(no_mangle)]
             pub extern "C" fn _start() -> ! {
-                ktest::init_harness($test_group);
+                ktest::init_harness_with_config($test_group, &___KLIB_CONFIG);
+                let boot_info = ktest::init_boot_info(___collect_boot_info());
+                if let Err(error) = ktest::memory::init(boot_info) {
+                    panic!("Memory initialization failed: {}", error);
+                }
 
                 if let Some(before_tests) = ___KLIB_CONFIG.before_tests {
-                    before_tests();
+                    before_tests(boot_info);
                 }
 
                 test_main();
@@ -118,19 +205,41 @@ macro_rules! klib {
     };
 }
 
+use crate::{memory::heap::{HEAP_SIZE, HEAP_START}, test::order::Shuffle};
+
 pub struct KlibConfig {
     #[cfg(not(feature = "limine"))]
     pub before_tests: Option<fn(&'static bootloader_api::BootInfo)>,
     #[cfg(feature = "limine")]
-    pub before_tests: Option<fn()>,
-    pub after_tests: Option<fn()>
+    pub before_tests: Option<fn(&'static crate::LimineBootInfo)>,
+    pub after_tests: Option<fn()>,
+    pub shuffle: Shuffle,
+    pub repeat: usize,
+    pub repeat_test: Option<&'static str>,
+    pub repeat_until_failure: bool,
+    pub max_failures: Option<usize>,
+    pub test_timeout_ms: Option<u64>,
+    pub verbose: bool,
+    pub heap_start: usize,
+    pub heap_size: u64,
+    pub fail_on_leaks: bool
 }
 
 impl KlibConfig {
     pub const fn new_default() -> Self {
         KlibConfig {
             before_tests: None,
-            after_tests: None
+            after_tests: None,
+            shuffle: Shuffle::Off,
+            repeat: 1,
+            repeat_test: None,
+            repeat_until_failure: false,
+            max_failures: None,
+            test_timeout_ms: None,
+            verbose: false,
+            heap_start: HEAP_START,
+            heap_size: HEAP_SIZE,
+            fail_on_leaks: false
         }
     }
 }
@@ -139,15 +248,35 @@ pub struct KlibConfigBuilder {
     #[cfg(not(feature = "limine"))]
     pub before_tests: Option<fn(&'static bootloader_api::BootInfo)>,
     #[cfg(feature = "limine")]
-    pub before_tests: Option<fn()>,
-    pub after_tests: Option<fn()>
+    pub before_tests: Option<fn(&'static crate::LimineBootInfo)>,
+    pub after_tests: Option<fn()>,
+    pub shuffle: Shuffle,
+    pub repeat: usize,
+    pub repeat_test: Option<&'static str>,
+    pub repeat_until_failure: bool,
+    pub max_failures: Option<usize>,
+    pub test_timeout_ms: Option<u64>,
+    pub verbose: bool,
+    pub heap_start: usize,
+    pub heap_size: u64,
+    pub fail_on_leaks: bool
 }
 
 impl KlibConfigBuilder {
     pub const fn new_default() -> Self {
         KlibConfigBuilder {
             before_tests: None,
-            after_tests: None
+            after_tests: None,
+            shuffle: Shuffle::Off,
+            repeat: 1,
+            repeat_test: None,
+            repeat_until_failure: false,
+            max_failures: None,
+            test_timeout_ms: None,
+            verbose: false,
+            heap_start: HEAP_START,
+            heap_size: HEAP_SIZE,
+            fail_on_leaks: false
         }
     }
 
@@ -155,22 +284,52 @@ impl KlibConfigBuilder {
     pub const fn new(before_tests: Option<fn(&'static bootloader_api::BootInfo)>, after_tests: Option<fn()>) -> Self {
         KlibConfigBuilder {
             before_tests,
-            after_tests
+            after_tests,
+            shuffle: Shuffle::Off,
+            repeat: 1,
+            repeat_test: None,
+            repeat_until_failure: false,
+            max_failures: None,
+            test_timeout_ms: None,
+            verbose: false,
+            heap_start: HEAP_START,
+            heap_size: HEAP_SIZE,
+            fail_on_leaks: false
         }
     }
 
     #[cfg(feature = "limine")]
-    pub const fn new(before_tests: Option<fn()>, after_tests: Option<fn()>) -> Self {
+    pub const fn new(before_tests: Option<fn(&'static crate::LimineBootInfo)>, after_tests: Option<fn()>) -> Self {
         KlibConfigBuilder {
             before_tests,
-            after_tests
+            after_tests,
+            shuffle: Shuffle::Off,
+            repeat: 1,
+            repeat_test: None,
+            repeat_until_failure: false,
+            max_failures: None,
+            test_timeout_ms: None,
+            verbose: false,
+            heap_start: HEAP_START,
+            heap_size: HEAP_SIZE,
+            fail_on_leaks: false
         }
     }
 
     pub const fn build(self) -> KlibConfig {
         KlibConfig {
             before_tests: self.before_tests,
-            after_tests: self.after_tests
+            after_tests: self.after_tests,
+            shuffle: self.shuffle,
+            repeat: self.repeat,
+            repeat_test: self.repeat_test,
+            repeat_until_failure: self.repeat_until_failure,
+            max_failures: self.max_failures,
+            test_timeout_ms: self.test_timeout_ms,
+            verbose: self.verbose,
+            heap_start: self.heap_start,
+            heap_size: self.heap_size,
+            fail_on_leaks: self.fail_on_leaks
         }
     }
 
@@ -181,7 +340,7 @@ impl KlibConfigBuilder {
     }
 
     #[cfg(feature = "limine")]
-    pub const fn before_tests(mut self, before_tests: fn()) -> Self {
+    pub const fn before_tests(mut self, before_tests: fn(&'static crate::LimineBootInfo)) -> Self {
         self.before_tests = Some(before_tests);
         self
     }
@@ -190,4 +349,77 @@ impl KlibConfigBuilder {
         self.after_tests = Some(after_tests);
         self
     }
+
+    /// Run tests in a random order. The seed is reported in the test group header so that
+    /// a failing order can be replayed with `shuffle_seed`.
+    pub const fn shuffle(mut self) -> Self {
+        self.shuffle = Shuffle::Random;
+        self
+    }
+
+    /// Run tests in the random order produced by the given seed.
+    pub const fn shuffle_seed(mut self, seed: u64) -> Self {
+        self.shuffle = Shuffle::Seed(seed);
+        self
+    }
+
+    /// Run each test `iterations` times, reporting every iteration and a pass rate per test.
+    pub const fn repeat(mut self, iterations: usize) -> Self {
+        self.repeat = iterations;
+        self
+    }
+
+    /// Only repeat the test with the given name (e.g. `"my_test"` or `"my_crate::tests::my_test"`).
+    pub const fn repeat_test(mut self, name: &'static str) -> Self {
+        self.repeat_test = Some(name);
+        self
+    }
+
+    /// Stop repeating a test after its first failure. Combined with a large `repeat`, this
+    /// runs a test until it fails.
+    pub const fn repeat_until_failure(mut self) -> Self {
+        self.repeat_until_failure = true;
+        self
+    }
+
+    /// Stop the run after `max_failures` test failures. The remaining tests are reported as
+    /// not run, and QEMU exits with the failure code.
+    pub const fn max_failures(mut self, max_failures: usize) -> Self {
+        self.max_failures = Some(max_failures);
+        self
+    }
+
+    /// Fail `async` tests whose future is still pending after `timeout_ms` milliseconds.
+    /// Individual tests can override this with `#[ktest(timeout = ms)]`.
+    pub const fn test_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.test_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Print the output captured from every test on serial, not only from failed tests.
+    pub const fn verbose(mut self) -> Self {
+        self.verbose = true;
+        self
+    }
+
+    /// Map the heap (with the `allocator` feature) at the given page-aligned virtual address,
+    /// instead of `memory::heap::HEAP_START`. Startup fails if the range is already mapped.
+    pub const fn heap_start(mut self, heap_start: usize) -> Self {
+        self.heap_start = heap_start;
+        self
+    }
+
+    /// Map a heap (with the `allocator` feature) of the given size in bytes, instead of
+    /// `memory::heap::HEAP_SIZE`. Tests can grow it further with `memory::heap::reserve`.
+    pub const fn heap_size(mut self, heap_size: u64) -> Self {
+        self.heap_size = heap_size;
+        self
+    }
+
+    /// Fail tests which leak resources (e.g. frames still allocated from a `FrameArena` when the
+    /// test ends), instead of only reporting the leak as a warning.
+    pub const fn fail_on_leaks(mut self) -> Self {
+        self.fail_on_leaks = true;
+        self
+    }
 }
@@ -1 +1,4 @@
-pub mod klib;
\ No newline at end of file
+pub mod assert;
+pub mod check;
+pub mod klib;
+pub mod skip;
\ No newline at end of file
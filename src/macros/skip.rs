@@ -0,0 +1,26 @@
+/// `skip!` function-like macro
+/// 
+/// Stops the current test and reports it as ignored with the given reason. Unlike `#[ignore]`,
+/// this is decided at runtime, so tests can opt out when the hardware they need is missing:
+/// 
+/// ```
+/// #[ktest]
+/// fn apic_timer() {
+///     if !apic_present() {
+///         ktest::skip!("no APIC present");
+///     }
+///     // ...
+/// }
+/// ```
+/// 
+/// The reason accepts the same format arguments as `panic!`. A skipped test is neither a pass
+/// nor a failure.
+#[macro_export]
+macro_rules! skip {
+    () => {
+        $crate::test::runner::skip(format_args!("skipped"))
+    };
+    ($($arg:tt)+) => {
+        $crate::test::runner::skip(format_args!($($arg)+))
+    };
+}
@@ -1,45 +1,346 @@
+use core::{ops::Range, sync::atomic::{AtomicUsize, Ordering}};
 use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
 use conquer_once::spin::OnceCell;
+use crate::alloc::FaultInjector;
 use spin::Mutex;
-use x86_64::{structures::paging::{FrameAllocator, PhysFrame, Size4KiB}, PhysAddr};
+use x86_64::{structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB}, PhysAddr};
 
-static FRAME_ALLOCATOR: OnceCell<Mutex<BumpFrameAllocator>> = OnceCell::uninit();
+/// The maximum number of usable memory regions; frames are not allocated from any further regions.
+pub const MAX_USABLE_REGIONS: usize = 128;
 
-pub fn get_frame_allocator(boot_info: &'static bootloader_api::BootInfo) -> &'static Mutex<BumpFrameAllocator> {
-    FRAME_ALLOCATOR.get_or_init(|| Mutex::new(init_frame_allocator(boot_info)))
+/// The maximum number of frames a `FrameArena` can have allocated at once.
+pub const MAX_ARENA_FRAMES: usize = 512;
+
+/// The shared frame allocator. It is built in place (it is too large to pass around on a boot
+/// stack), and only used once `FRAME_ALLOCATOR_INIT` is set.
+static FRAME_ALLOCATOR: Mutex<RegionFrameAllocator> = Mutex::new(RegionFrameAllocator::new());
+
+/// Set once the regions of the shared frame allocator have been added
+static FRAME_ALLOCATOR_INIT: OnceCell<()> = OnceCell::uninit();
+
+pub fn get_frame_allocator(boot_info: &'static bootloader_api::BootInfo) -> &'static Mutex<RegionFrameAllocator> {
+    let usable_regions = boot_info.memory_regions.iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable)
+        .map(|r| r.start..r.end);
+
+    get_frame_allocator_from_regions(boot_info.physical_memory_offset.into_option(), usable_regions)
+}
+
+/// Gets the shared frame allocator, initializing it from the given usable physical memory ranges
+/// (e.g. from the Limine memory map) if needed. Freed frames can only be reused if physical
+/// memory is mapped at `physical_memory_offset`.
+pub fn get_frame_allocator_from_regions(
+    physical_memory_offset: Option<u64>,
+    usable_regions: impl IntoIterator<Item = Range<u64>>
+) -> &'static Mutex<RegionFrameAllocator> {
+    FRAME_ALLOCATOR_INIT.get_or_init(|| unsafe {
+        FRAME_ALLOCATOR.lock().add_usable_regions(physical_memory_offset, usable_regions);
+    });
+    &FRAME_ALLOCATOR
+}
+
+/// Gets the shared frame allocator, initializing it from the `BootInfo` (or `LimineBootInfo`) given to
+/// ktest if needed.
+/// Returns `None` if it has not been initialized and no `BootInfo` is available.
+pub fn try_get_frame_allocator() -> Option<&'static Mutex<RegionFrameAllocator>> {
+    #[cfg(not(feature = "limine"))]
+    if let Some(boot_info) = crate::args::get_boot_info() {
+        return Some(get_frame_allocator(boot_info));
+    }
+    #[cfg(feature = "limine")]
+    if let Some(boot_info) = crate::args::get_boot_info() {
+        return Some(get_frame_allocator_from_regions(boot_info.hhdm_offset, boot_info.usable_regions()));
+    }
+
+    FRAME_ALLOCATOR_INIT.get().map(|_| &FRAME_ALLOCATOR)
 }
 
-fn init_frame_allocator(boot_info: &'static bootloader_api::BootInfo) -> BumpFrameAllocator {
-    unsafe { BumpFrameAllocator::init(&boot_info.memory_regions) }
+/// A frame allocator over the usable regions of the memory map. Each region keeps a cursor to its
+/// next unused frame, so allocation does not depend on how many frames were handed out before.
+///
+/// Freed frames are handed out again first. They are kept on a list linked through the frames
+/// themselves (each holds the address of the next), so any number can be freed without a fixed
+/// buffer; this needs physical memory to be mapped (`physical_memory_offset`). Without it, freed
+/// frames cannot be reused, which is counted by `lost_frames` and reported on serial.
+pub struct RegionFrameAllocator {
+    regions: heapless::Vec<Region, MAX_USABLE_REGIONS>,
+    /// The index of the first region which may have unused frames left
+    current: usize,
+    /// The virtual address at which physical memory is mapped, to reach the free list
+    physical_memory_offset: Option<u64>,
+    /// The most recently freed frame, which holds the physical address of the next (or `FREE_LIST_END`)
+    free: Option<PhysFrame>,
+    free_count: usize,
+    /// The number of freed frames which could not be reused, as physical memory is not mapped
+    lost: usize,
+    faults: FaultInjector
 }
 
-pub struct BumpFrameAllocator {
-    memory_map: &'static MemoryRegions,
-    next: usize
+/// The address stored in the last frame of the free list
+const FREE_LIST_END: u64 = u64::MAX;
+
+/// A usable region of physical memory, and the address of its next unused frame
+struct Region {
+    next: u64,
+    end: u64
 }
 
-impl BumpFrameAllocator {
-    pub unsafe fn init(memory_map: &'static MemoryRegions) -> Self {
-        BumpFrameAllocator {
-            memory_map,
-            next: 0,
+impl RegionFrameAllocator {
+    /// Creates a frame allocator without any memory.
+    pub const fn new() -> Self {
+        RegionFrameAllocator {
+            regions: heapless::Vec::new(),
+            current: 0,
+            physical_memory_offset: None,
+            free: None,
+            free_count: 0,
+            lost: 0,
+            faults: FaultInjector::new()
+        }
+    }
+
+    /// Creates a frame allocator over the usable regions of the bootloader's memory map.
+    /// 
+    /// # Safety
+    /// 
+    /// The memory map must be valid, as the frames of its usable regions are handed out without
+    /// checks, and physical memory must be mapped at `physical_memory_offset` (if given).
+    pub unsafe fn init(memory_map: &'static MemoryRegions, physical_memory_offset: Option<u64>) -> Self {
+        let usable_regions = memory_map.iter()
+            .filter(|r| r.kind == MemoryRegionKind::Usable)
+            .map(|r| r.start..r.end);
+
+        unsafe { Self::from_usable_regions(physical_memory_offset, usable_regions) }
+    }
+
+    /// Creates a frame allocator over the given usable physical memory ranges. Only the first
+    /// `MAX_USABLE_REGIONS` ranges are used.
+    /// 
+    /// # Safety
+    /// 
+    /// The ranges must be unused physical memory, as the frames are handed out without checks, and
+    /// physical memory must be mapped at `physical_memory_offset` (if given).
+    pub unsafe fn from_usable_regions(
+        physical_memory_offset: Option<u64>,
+        usable_regions: impl IntoIterator<Item = Range<u64>>
+    ) -> Self {
+        let mut allocator = Self::new();
+        unsafe { allocator.add_usable_regions(physical_memory_offset, usable_regions) };
+        allocator
+    }
+
+    /// Helper function to add usable ranges in place (see `from_usable_regions`)
+    unsafe fn add_usable_regions(
+        &mut self,
+        physical_memory_offset: Option<u64>,
+        usable_regions: impl IntoIterator<Item = Range<u64>>
+    ) {
+        self.physical_memory_offset = physical_memory_offset;
+        for region in usable_regions {
+            let region = Region { next: region.start.next_multiple_of(4096), end: region.end };
+            if self.regions.push(region).is_err() {
+                break; // only the first `MAX_USABLE_REGIONS` are used
+            }
         }
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.kind == MemoryRegionKind::Usable);
-        let addr_ranges = usable_regions.map(|r| r.start..r.end);
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    /// The number of frames which have been freed, and can be allocated again.
+    pub fn free_frames(&self) -> usize {
+        self.free_count
+    }
+
+    /// The number of freed frames which could not be kept for reuse, because physical memory is
+    /// not mapped.
+    pub fn lost_frames(&self) -> usize {
+        self.lost
+    }
+
+    /// Makes frame allocation fail after `frames` more successful allocations, until the faults are
+    /// cleared, to test out-of-memory handling in page-table code.
+    pub fn fail_after(&mut self, frames: usize) {
+        self.faults.fail_after(frames);
+    }
+
+    /// Makes each frame allocation fail with the given probability (from `0.0` to `1.0`), until
+    /// the faults are cleared. The same seed fails the same allocations.
+    pub fn fail_probability(&mut self, probability: f64, seed: u64) {
+        self.faults.fail_probability(probability, seed);
+    }
+
+    /// Stops failing frame allocations. The runner clears the faults of the shared frame allocator
+    /// before each test.
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Helper function to get a pointer to the link stored in a freed frame
+    fn link(&self, frame: PhysFrame, physical_memory_offset: u64) -> *mut u64 {
+        (physical_memory_offset + frame.start_address().as_u64()) as *mut u64
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BumpFrameAllocator {
+impl Default for RegionFrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for RegionFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        if self.faults.should_fail() {
+            return None;
+        }
+
+        if let (Some(frame), Some(physical_memory_offset)) = (self.free, self.physical_memory_offset) {
+            let next = unsafe { self.link(frame, physical_memory_offset).read_volatile() };
+            self.free = (next != FREE_LIST_END).then(|| PhysFrame::containing_address(PhysAddr::new(next)));
+            self.free_count -= 1;
+            return Some(frame);
+        }
+
+        while let Some(region) = self.regions.get_mut(self.current) {
+            if region.next + 4096 <= region.end {
+                let frame = PhysFrame::containing_address(PhysAddr::new(region.next));
+                region.next += 4096;
+                return Some(frame);
+            }
+            self.current += 1; // this region is used up
+        }
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for RegionFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let Some(physical_memory_offset) = self.physical_memory_offset else {
+            if self.lost == 0 {
+                crate::serial_println!("\nwarning: freed frames cannot be reused without a physical memory mapping");
+            }
+            self.lost += 1;
+            return;
+        };
+
+        // the frame is no longer in use, so it holds the link to the rest of the free list
+        let next = self.free.map_or(FREE_LIST_END, |next| next.start_address().as_u64());
+        unsafe { self.link(frame, physical_memory_offset).write_volatile(next) };
+        self.free = Some(frame);
+        self.free_count += 1;
+    }
+}
+
+/// The number of frames left outstanding by the arenas of the current test, which the runner
+/// reports as a `frame_leak`
+static LEAKED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of frames leaked by the current test, i.e. still allocated from its `FrameArena`s
+/// when they were torn down.
+pub fn leaked_frames() -> usize {
+    LEAKED_FRAMES.load(Ordering::SeqCst)
+}
+
+/// Stops failing allocations from the shared frame allocator (if it has been initialized).
+pub fn clear_faults() {
+    if FRAME_ALLOCATOR_INIT.is_initialized() {
+        FRAME_ALLOCATOR.lock().clear_faults();
+    }
+}
+
+/// The number of frames quarantined by `FrameArena`s over the whole run; see `quarantined_frames`
+static QUARANTINED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of frames which were still allocated from a `FrameArena` when it was torn down, over
+/// the whole run. These frames are never handed out again (they may still be in use, e.g. as page
+/// tables), so they are lost to the frame allocator.
+pub fn quarantined_frames() -> usize {
+    QUARANTINED_FRAMES.load(Ordering::SeqCst)
+}
+
+/// Forgets the frames leaked by the previous test. This is called by the runner before each test.
+pub fn reset_leaked_frames() {
+    LEAKED_FRAMES.store(0, Ordering::SeqCst);
+}
+
+/// A frame allocator for a single test, drawing frames from the shared frame allocator and
+/// tracking which frames it has handed out. Tests can request one as a fixture parameter:
+/// 
+/// ```
+/// #[ktest]
+/// fn maps_a_page(frames: &mut FrameArena) {
+///     let frame = frames.allocate_frame().unwrap();
+///     // ...
+/// }
+/// ```
+/// 
+/// When the arena is dropped (or, for a fixture, when the test ends or panics), frames which were
+/// not freed through it are reported as a `frame_leak` of the current test, and quarantined: they
+/// are not returned to the shared frame allocator, nor written to, as they may still be in use
+/// (e.g. the page tables created by `map_to`, which are never freed). An arena tracks up to
+/// `MAX_ARENA_FRAMES` frames at once; beyond that, allocation fails.
+pub struct FrameArena {
+    allocator: &'static Mutex<RegionFrameAllocator>,
+    frames: heapless::Vec<PhysFrame, MAX_ARENA_FRAMES>
+}
+
+impl FrameArena {
+    pub fn new() -> Self {
+        let allocator = try_get_frame_allocator()
+            .expect("Frame allocator is not initialized; BootInfo is required for FrameArena");
+
+        FrameArena {
+            allocator,
+            frames: heapless::Vec::new()
+        }
+    }
+
+    /// The number of frames allocated through this arena, and not yet freed.
+    pub fn allocated(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Quarantines every outstanding frame, reporting them as leaked; they are never handed out
+    /// again (see `quarantined_frames`). This is called when the arena is dropped, or torn down as
+    /// a fixture.
+    pub fn quarantine(&mut self) {
+        LEAKED_FRAMES.fetch_add(self.frames.len(), Ordering::SeqCst);
+        QUARANTINED_FRAMES.fetch_add(self.frames.len(), Ordering::SeqCst);
+        self.frames.clear();
+    }
+}
+
+impl Drop for FrameArena {
+    fn drop(&mut self) {
+        self.quarantine();
+    }
+}
+
+impl Default for FrameArena {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for FrameArena {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.frames.is_full() {
+            return None;
+        }
+
+        let frame = self.allocator.lock().allocate_frame()?;
+        let _ = self.frames.push(frame); // checked above
+        Some(frame)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for FrameArena {
+    /// Frees a frame allocated from this arena. Any other frame, or one which was already freed, is
+    /// ignored with a warning on serial, as freeing it could put it on the free list twice.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let Some(index) = self.frames.iter().position(|&allocated| allocated == frame) else {
+            crate::serial_println!("\nwarning: ignoring a free of frame {:#x}, which is not allocated from this FrameArena",
+                frame.start_address().as_u64());
+            return;
+        };
+        self.frames.swap_remove(index);
+        unsafe { self.allocator.lock().deallocate_frame(frame) };
     }
 }
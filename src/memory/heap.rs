@@ -1,14 +1,60 @@
 pub use linked_list_allocator::LockedHeap;
-use x86_64::{structures::paging::{mapper::MapToError, Size4KiB}};
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
 
+/// The default start address of the heap, used unless `KlibConfig` sets another.
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// The default size of the heap, used unless `KlibConfig` sets another.
 pub const HEAP_SIZE: u64 = 100 * 1024; // 100 KB
 
 #[global_allocator]
 #[cfg(feature = "allocator")]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static HEAP_ALLOCATOR: crate::alloc::TrackingHeap = crate::alloc::TrackingHeap::empty();
 
-pub fn init_allocator_if_enabled(_physical_memory_offset: Option<u64>) -> Result<(), MapToError<Size4KiB>> {
+/// The start address and mapped size of the heap, once it is initialized
+#[cfg(feature = "allocator")]
+static HEAP_RANGE: spin::Mutex<Option<(u64, u64)>> = spin::Mutex::new(None);
+
+/// An error while mapping the heap.
+#[derive(Debug)]
+pub enum HeapError {
+    /// The heap range is not page-aligned, or does not fit in the virtual address space.
+    InvalidRange { start: u64, size: u64 },
+    /// A page in the heap range is already mapped, e.g. to the kernel or the physical memory map.
+    Collision { start: u64, size: u64, page: u64 },
+    /// The heap has not been initialized, so it cannot grow.
+    NotInitialized,
+    /// The frame allocator or kernel page table has not been initialized.
+    NoFrameAllocator,
+    /// Mapping a heap page failed, e.g. because there are no frames left.
+    Mapping(MapToError<Size4KiB>)
+}
+
+impl core::fmt::Display for HeapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HeapError::InvalidRange { start, size } =>
+                write!(f, "heap range {:#x}..{:#x} is not page-aligned or not canonical", start, start.saturating_add(*size)),
+            HeapError::Collision { start, size, page } =>
+                write!(f, "heap range {:#x}..{:#x} collides with the existing mapping of page {:#x}; choose another heap_start", start, start + size, page),
+            HeapError::NotInitialized => write!(f, "the heap has not been initialized"),
+            HeapError::NoFrameAllocator => write!(f, "the frame allocator or kernel page table has not been initialized"),
+            HeapError::Mapping(MapToError::FrameAllocationFailed) => write!(f, "out of physical frames while mapping the heap"),
+            HeapError::Mapping(error) => write!(f, "mapping a heap page failed: {:?}", error)
+        }
+    }
+}
+
+impl From<MapToError<Size4KiB>> for HeapError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        HeapError::Mapping(error)
+    }
+}
+
+/// Maps and initializes the heap, at the start address and with the size from `KlibConfig`
+/// (`HEAP_START` and `HEAP_SIZE` by default), using the offset at which physical memory is mapped.
+/// Does nothing unless the `allocator` feature is enabled. This is called by `memory::init`,
+/// which reports a missing physical memory mapping as a `MemoryError`.
+pub fn init_allocator_if_enabled(_physical_memory_offset: u64) -> Result<(), HeapError> {
     #[cfg(not(feature = "allocator"))]
     {
         Ok(())
@@ -16,32 +62,99 @@ pub fn init_allocator_if_enabled(_physical_memory_offset: Option<u64>) -> Result
 
     #[cfg(feature = "allocator")]
     {
-        use crate::memory::{frame, paging};
-        use x86_64::{structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags}, VirtAddr};
+        use crate::memory::paging;
 
-        let page_range = {
-            let heap_start = VirtAddr::new(HEAP_START as u64);
-            let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let config = crate::args::get_klib_config();
+        let (start, size) = (config.heap_start as u64, config.heap_size.next_multiple_of(4096));
 
-            let heap_start_page = Page::containing_address(heap_start);
-            let heap_end_page = Page::containing_address(heap_end);
+        paging::get_kernel_page_table(_physical_memory_offset);
+        let mut heap_range = HEAP_RANGE.lock();
+        map_pages(start, size)?;
 
-            Page::range_inclusive(heap_start_page, heap_end_page)
-        };
+        unsafe { HEAP_ALLOCATOR.heap().lock().init(start as *mut u8, size as usize); }
+        *heap_range = Some((start, size));
+        Ok(())
+    }
+}
 
-        for page in page_range {
-            // important: locks are dropped at the end of each iteration (locks should be as microscopic as possible)
-            let mut mapper = paging::get_kernel_page_table(_physical_memory_offset).lock();
-            let mut frame_allocator = frame::get_frame_allocator(_physical_memory_offset).lock();
+/// Grows the heap to at least `size` bytes, by mapping more pages at its end. This lets a test
+/// (or `before_tests`) which needs a larger heap than the rest of the suite request one:
+///
+/// ```
+/// ktest::memory::heap::reserve(16 * 1024 * 1024).expect("no room for a 16 MiB heap");
+/// ```
+///
+/// Does nothing if the heap is already at least `size` bytes.
+#[cfg(feature = "allocator")]
+pub fn reserve(size: u64) -> Result<(), HeapError> {
+    let mut heap_range = HEAP_RANGE.lock();
+    let (start, mapped) = heap_range.ok_or(HeapError::NotInitialized)?;
+    if size <= mapped {
+        return Ok(());
+    }
 
-            let frame = frame_allocator.allocate_frame()
-                .ok_or(MapToError::FrameAllocationFailed)?;
-            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let additional = (size - mapped).next_multiple_of(4096);
+    map_pages(start + mapped, additional)?;
 
-            unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator)?.flush() };
-        }
+    unsafe { HEAP_ALLOCATOR.heap().lock().extend(additional as usize); }
+    *heap_range = Some((start, mapped + additional));
+    Ok(())
+}
 
-        unsafe { HEAP_ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE as usize); }
-        Ok(())
+/// The size of the heap in bytes, or `None` if it has not been initialized.
+#[cfg(feature = "allocator")]
+pub fn size() -> Option<u64> {
+    HEAP_RANGE.lock().map(|(_, size)| size)
+}
+
+/// Helper function to map the pages of the given (page-aligned) range to new frames. Every page
+/// is checked before any is mapped, so a collision leaves the range unmapped; if mapping fails
+/// partway (e.g. when frames run out), the pages mapped so far are unmapped again.
+#[cfg(feature = "allocator")]
+fn map_pages(start: u64, size: u64) -> Result<(), HeapError> {
+    use crate::memory::{frame, paging};
+    use x86_64::{structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Translate}, VirtAddr};
+
+    let end = start.checked_add(size).ok_or(HeapError::InvalidRange { start, size })?;
+    let (Ok(heap_start), Ok(heap_end)) = (VirtAddr::try_new(start), VirtAddr::try_new(end - 1)) else {
+        return Err(HeapError::InvalidRange { start, size });
+    };
+    if !heap_start.is_aligned(4096u64) || size == 0 {
+        return Err(HeapError::InvalidRange { start, size });
     }
+
+    let page_range = Page::<Size4KiB>::range_inclusive(Page::containing_address(heap_start), Page::containing_address(heap_end));
+    let mapper = paging::try_get_kernel_page_table().ok_or(HeapError::NoFrameAllocator)?;
+    let frame_allocator = frame::try_get_frame_allocator().ok_or(HeapError::NoFrameAllocator)?;
+
+    // `translate_addr` also finds pages inside huge (2 MiB or 1 GiB) mappings, such as the physical memory map
+    if let Some(page) = page_range.clone().find(|&page| mapper.lock().translate_addr(page.start_address()).is_some()) {
+        return Err(HeapError::Collision { start, size, page: page.start_address().as_u64() });
+    }
+
+    for (mapped, page) in page_range.enumerate() {
+        // important: locks are dropped at the end of each iteration (locks should be as microscopic as possible)
+        let mut mapper = mapper.lock();
+        let mut frame_allocator = frame_allocator.lock();
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let result = match frame_allocator.allocate_frame() {
+            Some(frame) => unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) }
+                .map(|flush| flush.flush())
+                .inspect_err(|_| unsafe { frame_allocator.deallocate_frame(frame) }),
+            None => Err(MapToError::FrameAllocationFailed)
+        };
+
+        if let Err(error) = result {
+            for page in page_range.take(mapped) {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+            }
+            return Err(error.into());
+        }
+    }
+
+    Ok(())
 }
@@ -1,3 +1,96 @@
 pub mod frame;
 pub mod heap;
-pub mod paging;
\ No newline at end of file
+pub mod paging;
+
+use core::ops::Range;
+use heap::HeapError;
+
+/// An error while initializing memory management for tests.
+#[derive(Debug)]
+pub enum MemoryError {
+    /// The bootloader did not map physical memory, so the page tables cannot be accessed.
+    NoPhysicalMemoryMapping,
+    /// The bootloader did not provide any usable memory, so there are no frames for the heap.
+    NoMemoryMap,
+    /// The heap could not be mapped.
+    Heap(HeapError)
+}
+
+impl core::fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            #[cfg(not(feature = "limine"))]
+            MemoryError::NoPhysicalMemoryMapping => write!(f, "BootInfo has no physical_memory_offset, which the allocator needs to \
+                access the page tables; map physical memory in the BootloaderConfig, e.g. \
+                `config.mappings.physical_memory = Some(Mapping::Dynamic)`"),
+            #[cfg(feature = "limine")]
+            MemoryError::NoPhysicalMemoryMapping => write!(f, "Limine did not answer the HHDM request, which the allocator needs to \
+                access the page tables"),
+            MemoryError::NoMemoryMap => write!(f, "the bootloader memory map has no usable regions"),
+            MemoryError::Heap(error) => write!(f, "{}", error)
+        }
+    }
+}
+
+impl From<HeapError> for MemoryError {
+    fn from(error: HeapError) -> Self {
+        MemoryError::Heap(error)
+    }
+}
+
+/// Initializes memory management from the bootloader's `BootInfo`: the frame allocator from
+/// `memory_regions`, the kernel page table from `physical_memory_offset`, and then the heap
+/// (with the `allocator` feature).
+/// 
+/// For example, in your main.rs:
+/// 
+/// ```
+/// ktest::memory::init(boot_info).expect("memory initialization failed");
+/// ```
+/// 
+/// Without the `allocator` feature, a missing physical memory mapping is not an error; only the
+/// frame allocator is initialized. If you are using the `klib!` macro, this function is called
+/// automatically.
+#[cfg(not(feature = "limine"))]
+pub fn init(boot_info: &'static bootloader_api::BootInfo) -> Result<(), MemoryError> {
+    use bootloader_api::info::MemoryRegionKind;
+
+    let usable_regions = boot_info.memory_regions.iter()
+        .filter(|region| region.kind == MemoryRegionKind::Usable)
+        .map(|region| region.start..region.end);
+
+    init_from_memory_map(boot_info.physical_memory_offset.into_option(), usable_regions)
+}
+
+/// Initializes memory management from the responses Limine gave to `klib!`: the frame allocator
+/// from the memory map, the kernel page table from the HHDM offset, and then the heap (with the
+/// `allocator` feature). If you are using the `klib!` macro, this function is called automatically.
+#[cfg(feature = "limine")]
+pub fn init(boot_info: &'static crate::LimineBootInfo) -> Result<(), MemoryError> {
+    init_from_memory_map(boot_info.hhdm_offset, boot_info.usable_regions())
+}
+
+/// Initializes memory management from the offset at which physical memory is mapped (e.g. the
+/// Limine HHDM offset) and the usable ranges of the physical memory map. This is what `init`
+/// does with a `BootInfo` or `LimineBootInfo`.
+pub fn init_from_memory_map(
+    physical_memory_offset: Option<u64>,
+    usable_regions: impl IntoIterator<Item = Range<u64>>
+) -> Result<(), MemoryError> {
+    let mut usable_regions = usable_regions.into_iter().peekable();
+    if usable_regions.peek().is_none() && cfg!(feature = "allocator") {
+        return Err(MemoryError::NoMemoryMap);
+    }
+    frame::get_frame_allocator_from_regions(physical_memory_offset, usable_regions);
+
+    let Some(physical_memory_offset) = physical_memory_offset else {
+        if cfg!(feature = "allocator") {
+            return Err(MemoryError::NoPhysicalMemoryMapping);
+        }
+        return Ok(());
+    };
+    paging::get_kernel_page_table(physical_memory_offset);
+    heap::init_allocator_if_enabled(physical_memory_offset)?;
+
+    Ok(())
+}
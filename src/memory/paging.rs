@@ -4,13 +4,19 @@ use x86_64::{structures::paging::{OffsetPageTable, PageTable}, VirtAddr};
 
 static KERNEL_PAGE_TABLE: OnceCell<Mutex<OffsetPageTable<'static>>> = OnceCell::uninit();
 
-pub fn get_kernel_page_table(physical_memory_offset: Option<u64>) -> &'static Mutex<OffsetPageTable<'static>> {
+/// Gets the kernel page table, initializing it from the offset at which physical memory is mapped
+/// (if it has not been initialized yet).
+pub fn get_kernel_page_table(physical_memory_offset: u64) -> &'static Mutex<OffsetPageTable<'static>> {
     KERNEL_PAGE_TABLE.get_or_init(|| Mutex::new(init_kernel_page_table(physical_memory_offset)))
 }
 
-fn init_kernel_page_table(physical_memory_offset: Option<u64>) -> OffsetPageTable<'static> {
-    let physical_memory_offset = VirtAddr::new(physical_memory_offset
-        .expect("BootInfo is missing physical_memory_offset"));
+/// Gets the kernel page table, if it has been initialized.
+pub fn try_get_kernel_page_table() -> Option<&'static Mutex<OffsetPageTable<'static>>> {
+    KERNEL_PAGE_TABLE.get()
+}
+
+fn init_kernel_page_table(physical_memory_offset: u64) -> OffsetPageTable<'static> {
+    let physical_memory_offset = VirtAddr::new(physical_memory_offset);
 
     unsafe {
         let level_4_table = find_level_4_table_from_bootloader(physical_memory_offset);
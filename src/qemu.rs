@@ -21,5 +21,71 @@ pub fn exit(exit_code: ExitCode) -> ! {
 #[repr(u32)]
 pub enum ExitCode {
     Success = 0x10,
-    _Failed = 0x11
-}
\ No newline at end of file
+    Failed = 0x11
+}
+
+/// Reads a file from the QEMU firmware configuration device (fw_cfg) into the given buffer,
+/// returning the file contents as a string. Files are passed to QEMU with, for example:
+/// 
+/// ```text
+/// -fw_cfg name=opt/ktest/repeat,string=50
+/// ```
+/// 
+/// Returns `None` if fw_cfg is not present, the file does not exist, or the contents are not
+/// valid UTF-8. Contents longer than the buffer are truncated.
+pub fn read_fw_cfg<'a>(name: &str, buffer: &'a mut [u8]) -> Option<&'a str> {
+    use x86_64::instructions::port::Port;
+
+    const SELECTOR_PORT: u16 = 0x510;
+    const DATA_PORT: u16 = 0x511;
+    const SIGNATURE_KEY: u16 = 0x0000;
+    const FILE_DIR_KEY: u16 = 0x0019;
+    const FILE_NAME_LENGTH: usize = 56;
+
+    let mut selector: Port<u16> = Port::new(SELECTOR_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    let mut read_bytes = |bytes: &mut [u8]| {
+        for byte in bytes.iter_mut() {
+            *byte = unsafe { data.read() };
+        }
+    };
+
+    // the signature is "QEMU" if the device is present
+    let mut signature = [0u8; 4];
+    unsafe { selector.write(SIGNATURE_KEY); }
+    read_bytes(&mut signature);
+    if &signature != b"QEMU" {
+        return None;
+    }
+
+    // the file directory is a big-endian count, followed by 64-byte entries
+    let mut count = [0u8; 4];
+    unsafe { selector.write(FILE_DIR_KEY); }
+    read_bytes(&mut count);
+
+    let mut file = None;
+    for _ in 0..u32::from_be_bytes(count) {
+        let mut size = [0u8; 4];
+        let mut key = [0u8; 2];
+        let mut reserved = [0u8; 2];
+        let mut file_name = [0u8; FILE_NAME_LENGTH];
+        read_bytes(&mut size);
+        read_bytes(&mut key);
+        read_bytes(&mut reserved);
+        read_bytes(&mut file_name);
+
+        let name_length = file_name.iter().position(|&b| b == 0).unwrap_or(FILE_NAME_LENGTH);
+        if &file_name[..name_length] == name.as_bytes() {
+            file = Some((u16::from_be_bytes(key), u32::from_be_bytes(size) as usize));
+            break;
+        }
+    }
+
+    let (key, size) = file?;
+    let length = size.min(buffer.len());
+    unsafe { selector.write(key); }
+    read_bytes(&mut buffer[..length]);
+
+    core::str::from_utf8(&buffer[..length]).ok()
+        .map(|contents| contents.trim_end_matches(['\0', '\n']))
+}
@@ -0,0 +1,33 @@
+use x86_64::instructions::random::RdRand;
+
+/// The SplitMix64 finalizer; scrambles the bits of the given value.
+pub const fn mix(value: u64) -> u64 {
+    let mut z = value;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Produces a seed from RDRAND, if available, otherwise from the current cycle count.
+pub fn hardware_seed() -> u64 {
+    if let Some(seed) = RdRand::new().and_then(|rdrand| rdrand.get_u64()) {
+        return seed;
+    }
+
+    mix(unsafe { core::arch::x86_64::_rdtsc() })
+}
+
+/// A SplitMix64 pseudo-random number generator. It is small and fast, and replays the same
+/// sequence for the same seed.
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub const fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mix(self.0)
+    }
+}
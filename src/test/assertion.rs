@@ -0,0 +1,256 @@
+use core::fmt::{Debug, Write};
+use heapless::{String, Vec};
+use spin::{Mutex, MutexGuard};
+
+/// The maximum length of each recorded value; longer values are truncated.
+pub const MAX_VALUE_LENGTH: usize = 256;
+
+/// The maximum length of each recorded location; longer locations are truncated.
+pub const MAX_LOCATION_LENGTH: usize = 128;
+
+/// The maximum length of the hexdump of a failed byte comparison; longer hexdumps are truncated.
+pub const MAX_HEXDUMP_LENGTH: usize = 768;
+
+/// The number of bytes in each row of the hexdump of a failed byte comparison.
+const HEXDUMP_ROW_LENGTH: usize = 16;
+
+/// The number of rows in the hexdump of a failed byte comparison, starting at the row of the
+/// first difference.
+const HEXDUMP_ROWS: usize = 3;
+
+/// The maximum number of soft assertion failures recorded per test; later failures are counted,
+/// but not recorded.
+pub const MAX_SOFT_FAILURES: usize = 32;
+
+/// The details of a failed structured assertion (e.g. `ktest::assert_eq!`), which the runner
+/// adds to the failure record of the current test.
+pub struct Assertion {
+    /// The source of the asserted expression, e.g. `a + b == 5`.
+    pub expression: String<MAX_VALUE_LENGTH>,
+    /// The expected value (the right-hand side, for `assert_eq!`).
+    pub expected: String<MAX_VALUE_LENGTH>,
+    /// The actual value (the left-hand side, for `assert_eq!`).
+    pub actual: String<MAX_VALUE_LENGTH>,
+    /// The offset of the first differing byte, for byte comparisons (e.g. `assert_bytes_eq!`).
+    pub offset: Option<usize>,
+    /// An aligned hexdump of the expected and actual bytes around the first difference, for
+    /// byte comparisons; otherwise empty.
+    pub hexdump: String<MAX_HEXDUMP_LENGTH>
+}
+
+/// The most recent failed assertion of the current test, taken by the runner when it panics.
+static LAST_ASSERTION: Mutex<Option<Assertion>> = Mutex::new(None);
+
+/// Takes the most recent failed assertion, if any.
+pub fn take_last_assertion() -> Option<Assertion> {
+    LAST_ASSERTION.lock().take()
+}
+
+/// Records a failed assertion and panics with the given message. This is used by the assertion
+/// macros, and is not considered part of the public API.
+#[doc(hidden)]
+#[track_caller]
+pub fn assert_failed(
+    kind: &str,
+    expression: &str,
+    expected: &dyn Debug,
+    actual: &dyn Debug,
+    message: Option<core::fmt::Arguments>
+) -> ! {
+    let mut assertion = Assertion {
+        expression: String::new(),
+        expected: String::new(),
+        actual: String::new(),
+        offset: None,
+        hexdump: String::new()
+    };
+    let _ = write!(Truncating(&mut assertion.expression), "{}", expression);
+    let _ = write!(Truncating(&mut assertion.expected), "{:?}", expected);
+    let _ = write!(Truncating(&mut assertion.actual), "{:?}", actual);
+    *LAST_ASSERTION.lock() = Some(assertion);
+
+    match message {
+        Some(message) => panic!("assertion `{}` failed: {}", kind, message),
+        None => panic!("assertion `{}` failed", kind)
+    }
+}
+
+/// Records a failed byte comparison, with a hexdump around the first difference, and panics with
+/// the given message. This is used by the memory assertion macros, and is not considered part of
+/// the public API.
+#[doc(hidden)]
+#[track_caller]
+pub fn bytes_failed(
+    kind: &str,
+    expression: &str,
+    expected: &[u8],
+    actual: &[u8],
+    message: Option<core::fmt::Arguments>
+) -> ! {
+    // bytes past the end of the shorter buffer differ, so the offset is always found for unequal buffers
+    let offset = expected.iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.len().min(actual.len()));
+    let row = offset - offset % HEXDUMP_ROW_LENGTH;
+
+    let mut assertion = Assertion {
+        expression: String::new(),
+        expected: String::new(),
+        actual: String::new(),
+        offset: Some(offset),
+        hexdump: String::new()
+    };
+    let _ = write!(Truncating(&mut assertion.expression), "{}", expression);
+    let _ = write!(Truncating(&mut assertion.expected), "{} bytes, {:#010x}: {}", expected.len(), row, HexRow(expected, row));
+    let _ = write!(Truncating(&mut assertion.actual), "{} bytes, {:#010x}: {}", actual.len(), row, HexRow(actual, row));
+
+    let end = expected.len().max(actual.len());
+    let mut hexdump = Truncating(&mut assertion.hexdump);
+    for row in (row..end).step_by(HEXDUMP_ROW_LENGTH).take(HEXDUMP_ROWS) {
+        let _ = writeln!(hexdump, "{:#010x}  expected  {}", row, HexRow(expected, row));
+        let _ = writeln!(hexdump, "              actual  {}", HexRow(actual, row));
+
+        let mut markers = String::<{ HEXDUMP_ROW_LENGTH * 3 }>::new();
+        for i in row..(row + HEXDUMP_ROW_LENGTH).min(end) {
+            let marker = if expected.get(i) != actual.get(i) { "^^ " } else { "   " };
+            let _ = markers.push_str(marker);
+        }
+        if markers.contains('^') {
+            let _ = writeln!(hexdump, "                      {}", markers.trim_end());
+        }
+    }
+    *LAST_ASSERTION.lock() = Some(assertion);
+
+    match message {
+        Some(message) => panic!("assertion `{}` failed: {}", kind, message),
+        None => panic!("assertion `{}` failed", kind)
+    }
+}
+
+/// Reads the memory compared by `assert_memory_eq!`. The macro calls this outside of an `unsafe`
+/// block, so that its caller has to write one. This is not considered part of the public API.
+///
+/// # Safety
+///
+/// The pointer must be valid for reads of `len` bytes.
+#[doc(hidden)]
+pub unsafe fn read_memory<'a>(pointer: *const u8, len: usize) -> &'a [u8] {
+    unsafe { core::slice::from_raw_parts(pointer, len) }
+}
+
+/// A failed soft assertion (e.g. `ktest::check!`), recorded without stopping the test.
+pub struct SoftFailure {
+    /// Where the soft assertion failed, e.g. `src/lib.rs:42`.
+    pub location: String<MAX_LOCATION_LENGTH>,
+    /// Why the soft assertion failed.
+    pub message: String<MAX_VALUE_LENGTH>
+}
+
+/// The failed soft assertions of the current test.
+pub struct SoftFailures {
+    failures: Vec<SoftFailure, MAX_SOFT_FAILURES>,
+    dropped: usize
+}
+
+impl SoftFailures {
+    const fn new() -> Self {
+        SoftFailures { failures: Vec::new(), dropped: 0 }
+    }
+
+    /// Whether no soft assertions have failed.
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty() && self.dropped == 0
+    }
+
+    /// The number of failed soft assertions, including those which were not recorded.
+    pub fn len(&self) -> usize {
+        self.failures.len() + self.dropped
+    }
+
+    /// The number of failed soft assertions which were not recorded, because the buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// The recorded soft assertion failures, in the order they failed.
+    pub fn iter(&self) -> impl Iterator<Item = &SoftFailure> {
+        self.failures.iter()
+    }
+
+    /// Forgets every soft assertion failure.
+    pub fn clear(&mut self) {
+        self.failures.clear();
+        self.dropped = 0;
+    }
+}
+
+/// The failed soft assertions of the current test, cleared by the runner before each test.
+static SOFT_FAILURES: Mutex<SoftFailures> = Mutex::new(SoftFailures::new());
+
+/// Locks the failed soft assertions of the current test.
+pub fn soft_failures() -> MutexGuard<'static, SoftFailures> {
+    SOFT_FAILURES.lock()
+}
+
+/// Takes the failed soft assertions of the current test, leaving none behind.
+pub fn take_soft_failures() -> SoftFailures {
+    core::mem::replace(&mut *SOFT_FAILURES.lock(), SoftFailures::new())
+}
+
+/// Records a failed soft assertion at the caller's location, without stopping the test. This is
+/// used by the soft assertion macros, and is not considered part of the public API.
+#[doc(hidden)]
+#[track_caller]
+pub fn record_soft_failure(message: core::fmt::Arguments) {
+    let caller = core::panic::Location::caller();
+
+    let mut failure = SoftFailure {
+        location: String::new(),
+        message: String::new()
+    };
+    let _ = write!(Truncating(&mut failure.location), "{}:{}", caller.file(), caller.line());
+    let _ = write!(Truncating(&mut failure.message), "{}", message);
+
+    // backslashes in locations are replaced, the same as in the failure record
+    for byte in unsafe { failure.location.as_mut_vec() }.iter_mut() {
+        if *byte == b'\\' {
+            *byte = b'/';
+        }
+    }
+
+    let mut soft_failures = SOFT_FAILURES.lock();
+    if let Err(_failure) = soft_failures.failures.push(failure) {
+        soft_failures.dropped += 1;
+    }
+}
+
+/// Helper to write one hexdump row of a buffer, with `--` for bytes past its end
+struct HexRow<'a>(&'a [u8], usize);
+
+impl core::fmt::Display for HexRow<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for i in self.1..self.1 + HEXDUMP_ROW_LENGTH {
+            let separator = if i == self.1 { "" } else { " " };
+            match self.0.get(i) {
+                Some(byte) => write!(f, "{}{:02x}", separator, byte)?,
+                None => write!(f, "{}--", separator)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Helper to write into a heapless String, dropping whatever does not fit (instead of failing)
+struct Truncating<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> Write for Truncating<'_, N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if self.0.push(c).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
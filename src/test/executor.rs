@@ -0,0 +1,79 @@
+use core::{future::Future, pin::pin, sync::atomic::{AtomicBool, Ordering}, task::{Context, Poll, RawWaker, RawWakerVTable, Waker}};
+use x86_64::instructions::interrupts;
+use crate::{cpu, test::{options, runner::{TestRunner, TEST_RUNNER}}};
+
+/// Set when the future being driven has been woken, and should be polled again.
+static WOKEN: AtomicBool = AtomicBool::new(false);
+
+static WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake, drop_waker);
+
+/// Drives a future to completion on the current CPU. This is used by `#[ktest]` for
+/// `async fn` tests, and is not considered part of the public API.
+///
+/// While the future is pending, the CPU halts until the next interrupt (if interrupts are
+/// enabled), so futures woken from interrupt handlers make progress without busy waiting. If
+/// the test has a timeout (`#[ktest(timeout = ms)]` or the `test_timeout_ms` option), the CPU
+/// spins against the deadline instead of halting, and the test fails if the future is still
+/// pending when it expires, whether or not any interrupt arrives.
+#[doc(hidden)]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = unsafe { Waker::from_raw(raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+
+    let timeout_ms = TEST_RUNNER.get()
+        .and_then(|runner| runner.current_test())
+        .and_then(|test| options::get().timeout_ms(test));
+    let deadline = timeout_ms.map(|timeout_ms| read_current_cycle() + timeout_ms * cpu::tsc_frequency() / 1000);
+
+    WOKEN.store(true, Ordering::SeqCst); // always poll once
+    loop {
+        if WOKEN.swap(false, Ordering::SeqCst)
+            && let Poll::Ready(output) = future.as_mut().poll(&mut context)
+        {
+            return output;
+        }
+
+        if deadline.is_some_and(|deadline| read_current_cycle() >= deadline) {
+            panic!("future did not complete within the test timeout ({} ms)", timeout_ms.unwrap_or(0));
+        }
+
+        wait_for_wake(deadline.is_some());
+    }
+}
+
+/// Waits until the next interrupt, or spins briefly if interrupts are disabled or the wait is
+/// bounded by a deadline (as a halted CPU could not check it without an interrupt).
+fn wait_for_wake(has_deadline: bool) {
+    if has_deadline || !interrupts::are_enabled() {
+        core::hint::spin_loop();
+        return;
+    }
+
+    // disable interrupts while checking, so a wake between the check and `hlt` is not missed
+    interrupts::disable();
+    if WOKEN.load(Ordering::SeqCst) {
+        interrupts::enable();
+    } else {
+        interrupts::enable_and_hlt();
+    }
+}
+
+fn raw_waker() -> RawWaker {
+    RawWaker::new(core::ptr::null(), &WAKER_VTABLE)
+}
+
+fn clone_waker(_: *const ()) -> RawWaker {
+    raw_waker()
+}
+
+fn wake(_: *const ()) {
+    WOKEN.store(true, Ordering::SeqCst);
+}
+
+fn drop_waker(_: *const ()) {}
+
+/// Helper function to read the current CPU cycle count using the RDTSC instruction.
+fn read_current_cycle() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
@@ -0,0 +1,110 @@
+use heapless::Vec;
+use spin::Mutex;
+
+/// A resource which is set up before a test body runs and torn down after it, even if the body
+/// panics.
+///
+/// Fixtures are requested as test function parameters; `#[ktest]` builds each one with
+/// `setup`, passes it to the test by reference, and calls `teardown` afterwards:
+///
+/// ```
+/// #[ktest]
+/// fn maps_a_page(frames: &mut FrameArena, boot_info: &BootInfo) {
+///     // ...
+/// }
+/// ```
+///
+/// A `&mut F` parameter requests the fixture `F`. A `&T` parameter requests a shared fixture of
+/// type `&'static T`, such as `&'static BootInfo`. Fixtures are torn down in the reverse order
+/// they were set up.
+pub trait Fixture: Sized {
+    /// Builds the fixture before the test body runs.
+    fn setup() -> Self;
+
+    /// Releases the fixture after the test body runs (or panics).
+    fn teardown(&mut self) {}
+}
+
+/// The maximum number of fixtures which can be alive at once.
+const MAX_FIXTURES: usize = 16;
+
+/// A type-erased pointer to a live fixture, with the function that tears it down.
+struct Registration {
+    fixture: *mut (),
+    teardown: unsafe fn(*mut ())
+}
+
+// safety: registrations are only created and used by the (single-threaded) test runner
+unsafe impl Send for Registration {}
+
+/// The fixtures of the current test which have not been torn down yet. When a test panics,
+/// its stack frames are never unwound (the panic handler runs on top of them), so these pointers
+/// stay valid until the runner tears them down, before it resumes on its own stack.
+static FIXTURES: Mutex<Vec<Registration, MAX_FIXTURES>> = Mutex::new(Vec::new());
+
+/// Sets up a fixture, runs the given body with it, and tears it down afterwards. If the body
+/// panics, the runner tears the fixture down instead.
+///
+/// This is used by `#[ktest]` for each fixture parameter, and is not considered part of the
+/// public API.
+#[doc(hidden)]
+pub fn with<F: Fixture, R>(body: impl FnOnce(&mut F) -> R) -> R {
+    let mut fixture = F::setup();
+
+    let registration = Registration {
+        fixture: &mut fixture as *mut F as *mut (),
+        teardown: teardown_erased::<F>
+    };
+    if FIXTURES.lock().push(registration).is_err() {
+        panic!("too many fixtures (the maximum is {})", MAX_FIXTURES);
+    }
+
+    let result = body(&mut fixture);
+
+    FIXTURES.lock().pop();
+    fixture.teardown();
+    result
+}
+
+/// Tears down every fixture which is still alive, most recent first. The runner calls this when
+/// a test stops early (by panicking or skipping).
+pub fn teardown_all() {
+    // each fixture is removed before its teardown runs, so a panicking teardown is not repeated
+    loop {
+        let Some(registration) = FIXTURES.lock().pop() else {
+            break;
+        };
+
+        unsafe { (registration.teardown)(registration.fixture) };
+    }
+}
+
+unsafe fn teardown_erased<F: Fixture>(fixture: *mut ()) {
+    unsafe { (*(fixture as *mut F)).teardown() };
+}
+
+#[cfg(not(feature = "limine"))]
+impl Fixture for &'static bootloader_api::BootInfo {
+    fn setup() -> Self {
+        crate::args::get_boot_info()
+            .expect("BootInfo is not available; use `klib!` or call `ktest::init_boot_info`")
+    }
+}
+
+#[cfg(feature = "limine")]
+impl Fixture for &'static crate::LimineBootInfo {
+    fn setup() -> Self {
+        crate::args::get_boot_info()
+            .expect("LimineBootInfo is not available; use `klib!` or call `ktest::init_boot_info`")
+    }
+}
+
+impl Fixture for crate::memory::frame::FrameArena {
+    fn setup() -> Self {
+        crate::memory::frame::FrameArena::new()
+    }
+
+    fn teardown(&mut self) {
+        self.quarantine();
+    }
+}
@@ -4,6 +4,11 @@ use crate::test::outcome::Termination;
 pub mod output;
 pub mod runner;
 pub mod outcome;
+pub mod order;
+pub mod options;
+pub mod fixture;
+pub mod executor;
+pub mod assertion;
 
 /// A standard test.
 ///
@@ -20,8 +25,20 @@ pub struct Test<T> {
     pub test: fn() -> T,
     /// Whether the test should be excluded. This is set by the `#[ignore]` attribute.
     pub ignore: Ignore,
+    /// Why the test is excluded. This is set by the `#[ignore = "reason"]` attribute.
+    pub ignore_reason: Option<&'static str>,
     /// Whether the test is expected to panic. This is set by the `#[should_panic]` attribute.
     pub should_panic: ShouldPanic,
+    /// How many times the test should be run. This is set by `#[ktest(repeat = N)]`.
+    pub repeat: usize,
+    /// How many times a failing test is retried. This is set by `#[ktest(retries = N)]`.
+    pub retries: usize,
+    /// The CPU features the test requires. This is set by `#[ktest(requires = "...")]`.
+    pub requires: &'static [&'static str],
+    /// Why the test is expected to fail, if it is. This is set by `#[ktest(xfail = "reason")]`.
+    pub xfail: Option<&'static str>,
+    /// The test timeout in milliseconds, if any. This is set by `#[ktest(timeout = ms)]`.
+    pub timeout_ms: Option<u64>,
 }
 
 /// A trait representing a test case that can be run and provides metadata about itself.
@@ -52,6 +69,18 @@ pub trait TestCase {
     /// Runs the test case. This should not panic if the test passes.
     fn run(&self) -> ();
 
+    /// The number of cases of a parameterized test, or `None` if the test is not parameterized.
+    /// Each case is run and reported on its own, with an indexed name like `my_test[0]`.
+    fn cases(&self) -> Option<usize> {
+        None
+    }
+
+    /// Runs a single case of a parameterized test. For tests which are not parameterized, this
+    /// is the same as `run`.
+    fn run_case(&self, _case: usize) {
+        self.run();
+    }
+
     /// Whether the test should be excluded or not.
     ///
     /// If this method returns `Ignore::Yes`, the test function will not be run at all (but it will
@@ -59,8 +88,46 @@ pub trait TestCase {
     /// disabled.
     fn ignore(&self) -> Ignore;
 
+    /// The reason the test is excluded, if one was given with `#[ignore = "reason"]`.
+    fn ignore_reason(&self) -> Option<&'static str> {
+        None
+    }
+
     /// Whether the test is expected to panic.
     fn should_panic(&self) -> ShouldPanic;
+
+    /// The reason the test is expected to fail, if it is. This is set by `#[ktest(xfail = "reason")]`.
+    ///
+    /// A failure or panic in such a test is reported as an expected failure (`xfail`), and a pass
+    /// is reported as an unexpected pass (`xpass`).
+    fn xfail(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// The CPU features the test requires, such as `"x2apic"` or `"1gb-pages"`. This is set by
+    /// `#[ktest(requires = "...")]`. If any feature is missing, the test is reported as ignored.
+    fn requires(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// The test timeout in milliseconds, if any. This is set by `#[ktest(timeout = ms)]`, and
+    /// overrides the runner's `test_timeout_ms` option. It is enforced while an `async` test
+    /// waits on its future.
+    fn timeout_ms(&self) -> Option<u64> {
+        None
+    }
+
+    /// How many times the test should be run. This is set by `#[ktest(repeat = N)]`; the
+    /// runner's `repeat` option can raise it, but never lower it.
+    fn repeat(&self) -> usize {
+        1
+    }
+
+    /// How many times a failing test is retried before it counts as a failure. This is set by
+    /// `#[ktest(retries = N)]`. A test which passes after a failed attempt is reported as flaky.
+    fn retries(&self) -> usize {
+        0
+    }
 }
 
 impl<T> TestCase for Test<T>
@@ -87,9 +154,139 @@ where
         self.ignore
     }
 
+    fn ignore_reason(&self) -> Option<&'static str> {
+        self.ignore_reason
+    }
+
     fn should_panic(&self) -> ShouldPanic {
         self.should_panic
     }
+
+    fn repeat(&self) -> usize {
+        self.repeat
+    }
+
+    fn retries(&self) -> usize {
+        self.retries
+    }
+
+    fn requires(&self) -> &'static [&'static str] {
+        self.requires
+    }
+
+    fn xfail(&self) -> Option<&'static str> {
+        self.xfail
+    }
+
+    fn timeout_ms(&self) -> Option<u64> {
+        self.timeout_ms
+    }
+}
+
+/// A parameterized test, run once for each case in a table of inputs.
+///
+/// This struct is created by the `#[ktest(cases = [...])]` attribute. Each case is reported as its
+/// own test, named with its index in the table (e.g. `my_test[0]`), so cases pass or fail on their
+/// own. The attribute passes the values of each case as the test parameters (a case with several
+/// parameters is written as a tuple):
+///
+/// ```
+/// #[ktest(cases = [(0x1, true), (0x0, false)])]
+/// fn check_present(flags: u64, present: bool) {
+///     assert_eq!(flags & 0x1 != 0, present);
+/// }
+/// ```
+///
+/// This struct is not to be used directly and is not considered part of the public API.
+#[doc(hidden)]
+pub struct ParamTest<P: 'static, T> {
+    /// The test name, without a case index.
+    pub name: &'static str,
+    /// The module path of the test.
+    pub modules: &'static str,
+    /// The test function itself, which is given one case at a time.
+    pub test: fn(&'static P) -> T,
+    /// The cases to run the test with.
+    pub cases: &'static [P],
+    /// Whether the test should be excluded. This is set by the `#[ignore]` attribute.
+    pub ignore: Ignore,
+    /// Why the test is excluded. This is set by the `#[ignore = "reason"]` attribute.
+    pub ignore_reason: Option<&'static str>,
+    /// Whether the test is expected to panic. This is set by the `#[should_panic]` attribute.
+    pub should_panic: ShouldPanic,
+    /// How many times each case should be run. This is set by `#[ktest(repeat = N)]`.
+    pub repeat: usize,
+    /// How many times a failing case is retried. This is set by `#[ktest(retries = N)]`.
+    pub retries: usize,
+    /// The CPU features the test requires. This is set by `#[ktest(requires = "...")]`.
+    pub requires: &'static [&'static str],
+    /// Why the test is expected to fail, if it is. This is set by `#[ktest(xfail = "reason")]`.
+    pub xfail: Option<&'static str>,
+    /// The test timeout in milliseconds, if any. This is set by `#[ktest(timeout = ms)]`.
+    pub timeout_ms: Option<u64>,
+}
+
+impl<P, T> TestCase for ParamTest<P, T>
+where
+    T: Termination,
+{
+    fn run(&self) {
+        for case in 0..self.cases.len() {
+            self.run_case(case);
+        }
+    }
+
+    fn cases(&self) -> Option<usize> {
+        Some(self.cases.len())
+    }
+
+    fn run_case(&self, case: usize) {
+        (self.test)(&self.cases[case]).terminate();
+    }
+
+    fn qualified_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn modules(&self) -> Option<&'static str> {
+        Some(self.modules)
+    }
+
+    fn ignore(&self) -> Ignore {
+        self.ignore
+    }
+
+    fn ignore_reason(&self) -> Option<&'static str> {
+        self.ignore_reason
+    }
+
+    fn should_panic(&self) -> ShouldPanic {
+        self.should_panic
+    }
+
+    fn repeat(&self) -> usize {
+        self.repeat
+    }
+
+    fn retries(&self) -> usize {
+        self.retries
+    }
+
+    fn requires(&self) -> &'static [&'static str] {
+        self.requires
+    }
+
+    fn xfail(&self) -> Option<&'static str> {
+        self.xfail
+    }
+
+    fn timeout_ms(&self) -> Option<u64> {
+        self.timeout_ms
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -97,7 +294,10 @@ pub enum Ignore {
     /// The test should be run.
     No,
     /// The test should not be run.
-    Yes
+    Yes,
+    /// The test should not be run, for the reason given by `TestCase::ignore_reason`. This is
+    /// set by the `#[ignore = "reason"]` attribute.
+    YesWithMessage
 }
 
 #[derive(Clone, Copy, Debug)]
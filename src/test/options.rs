@@ -0,0 +1,120 @@
+use conquer_once::spin::OnceCell;
+use heapless::String;
+use crate::{MAX_STRING_LENGTH, args, qemu, test::TestCase};
+
+/// Runner options, resolved once from `KlibConfig` and then overridden by any QEMU fw_cfg
+/// files under `opt/ktest/`. This allows the same test kernel to be run in different modes
+/// without rebuilding, e.g.:
+/// 
+/// ```text
+/// -fw_cfg name=opt/ktest/repeat,string=50 -fw_cfg name=opt/ktest/repeat_test,string=my_test
+/// ```
+pub struct RunOptions {
+    /// How many times each selected test is run.
+    pub repeat: usize,
+    /// The test to repeat; if `None`, every test is repeated.
+    pub repeat_test: Option<String<MAX_STRING_LENGTH>>,
+    /// Whether a repeated test stops repeating after its first failure.
+    pub repeat_until_failure: bool,
+    /// The number of failures after which the run stops; if `None`, the run never stops early.
+    pub max_failures: Option<usize>,
+    /// The default test timeout in milliseconds; if `None`, tests have no timeout.
+    pub test_timeout_ms: Option<u64>,
+    /// Whether the output captured from passing tests is printed on serial, as for failed tests.
+    pub verbose: bool,
+    /// Whether tests which leak resources fail, instead of only reporting the leak as a warning.
+    pub fail_on_leaks: bool
+}
+
+static RUN_OPTIONS: OnceCell<RunOptions> = OnceCell::uninit();
+
+/// Resolves the runner options. Only the first call has any effect.
+pub fn init() {
+    RUN_OPTIONS.get_or_init(resolve);
+}
+
+/// Gets the runner options, resolving them if needed.
+pub fn get() -> &'static RunOptions {
+    RUN_OPTIONS.get_or_init(resolve)
+}
+
+impl RunOptions {
+    /// The number of iterations to run for the given test, including per-test overrides.
+    pub fn iterations(&self, test: &dyn TestCase) -> usize {
+        let selected = match &self.repeat_test {
+            Some(name) => matches_test(test, name),
+            None => true
+        };
+
+        let global = if selected { self.repeat } else { 1 };
+        global.max(test.repeat()).max(1)
+    }
+
+    /// The timeout in milliseconds for the given test, including per-test overrides.
+    pub fn timeout_ms(&self, test: &dyn TestCase) -> Option<u64> {
+        test.timeout_ms().or(self.test_timeout_ms)
+    }
+}
+
+fn resolve() -> RunOptions {
+    let config = args::get_klib_config();
+    let mut options = RunOptions {
+        repeat: config.repeat,
+        repeat_test: config.repeat_test.map(|name| name.try_into().unwrap()),
+        repeat_until_failure: config.repeat_until_failure,
+        max_failures: config.max_failures,
+        test_timeout_ms: config.test_timeout_ms,
+        verbose: config.verbose,
+        fail_on_leaks: config.fail_on_leaks
+    };
+
+    let mut buffer = [0u8; MAX_STRING_LENGTH];
+    if let Some(repeat) = qemu::read_fw_cfg("opt/ktest/repeat", &mut buffer).and_then(|s| s.trim().parse().ok()) {
+        options.repeat = repeat;
+    }
+    if let Some(repeat_test) = qemu::read_fw_cfg("opt/ktest/repeat_test", &mut buffer) {
+        options.repeat_test = repeat_test.trim().try_into().ok();
+    }
+    if let Some(until_failure) = qemu::read_fw_cfg("opt/ktest/repeat_until_failure", &mut buffer) {
+        options.repeat_until_failure = parse_bool(until_failure);
+    }
+    if let Some(max_failures) = qemu::read_fw_cfg("opt/ktest/max_failures", &mut buffer).and_then(|s| s.trim().parse().ok()) {
+        options.max_failures = match max_failures {
+            0 => None, // 0 disables fail-fast, so it can be turned off without a rebuild
+            max_failures => Some(max_failures)
+        };
+    }
+    if let Some(timeout_ms) = qemu::read_fw_cfg("opt/ktest/timeout_ms", &mut buffer).and_then(|s| s.trim().parse().ok()) {
+        options.test_timeout_ms = match timeout_ms {
+            0 => None,
+            timeout_ms => Some(timeout_ms)
+        };
+    }
+    if let Some(verbose) = qemu::read_fw_cfg("opt/ktest/verbose", &mut buffer) {
+        options.verbose = parse_bool(verbose);
+    }
+    if let Some(fail_on_leaks) = qemu::read_fw_cfg("opt/ktest/fail_on_leaks", &mut buffer) {
+        options.fail_on_leaks = parse_bool(fail_on_leaks);
+    }
+
+    options
+}
+
+/// Whether the given name refers to the test, either by function name or by qualified name.
+fn matches_test(test: &dyn TestCase, name: &str) -> bool {
+    if test.name() == name {
+        return true;
+    }
+
+    match test.modules() {
+        Some(modules) => name.len() == modules.len() + 2 + test.name().len()
+            && name.starts_with(modules)
+            && name[modules.len()..].starts_with("::")
+            && name.ends_with(test.name()),
+        None => false
+    }
+}
+
+fn parse_bool(value: &str) -> bool {
+    matches!(value.trim(), "1" | "true" | "yes" | "on")
+}
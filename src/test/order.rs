@@ -0,0 +1,66 @@
+use conquer_once::spin::OnceCell;
+use crate::random;
+
+/// The order in which tests are run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shuffle {
+    /// Tests run in declaration order.
+    Off,
+    /// Tests run in a random order, seeded from RDRAND (or the TSC if RDRAND is unavailable).
+    Random,
+    /// Tests run in a random order derived from the given seed. Use this to replay the order
+    /// reported by a previous run.
+    Seed(u64)
+}
+
+/// The resolved shuffle seed for this run, or `None` if tests run in declaration order.
+static SEED: OnceCell<Option<u64>> = OnceCell::uninit();
+
+/// Resolves the shuffle seed for this run. Only the first call has any effect.
+pub fn init(shuffle: Shuffle) {
+    SEED.get_or_init(|| match shuffle {
+        Shuffle::Off => None,
+        Shuffle::Random => Some(random::hardware_seed()),
+        Shuffle::Seed(seed) => Some(seed)
+    });
+}
+
+/// Gets the shuffle seed for this run, if tests are shuffled.
+pub fn seed() -> Option<u64> {
+    SEED.get().copied().flatten()
+}
+
+/// Maps a run position to the index of the test (in declaration order) that runs there.
+///
+/// The shuffle is a seeded permutation computed on demand, so no buffer of test indices
+/// is needed. Because it is a pure function of the seed, a reported seed replays the exact
+/// same order.
+pub fn test_index(position: usize, test_count: usize) -> usize {
+    match seed() {
+        Some(seed) if test_count > 1 => permute(position, test_count, seed),
+        _ => position
+    }
+}
+
+/// A keyed bijection on `0..len`, built from a small Feistel network over the smallest
+/// power-of-two domain covering `len`. Values outside of `0..len` are walked through the
+/// permutation again until they land back inside the range (cycle walking).
+fn permute(index: usize, len: usize, seed: u64) -> usize {
+    let bits = (usize::BITS - (len - 1).leading_zeros()).max(2);
+    let half = bits.div_ceil(2);
+    let mask = (1u64 << half) - 1;
+
+    let mut value = index as u64;
+    loop {
+        let (mut left, mut right) = (value >> half, value & mask);
+        for round in 0..4u64 {
+            let f = random::mix(right ^ seed ^ (round << 32)) & mask;
+            (left, right) = (right, left ^ f);
+        }
+        value = (left << half) | right;
+
+        if value < len as u64 {
+            return value as usize;
+        }
+    }
+}
@@ -39,18 +39,42 @@ where
 pub enum TestResult {
     Success,
     Failure,
-    Ignore
+    Ignore,
+    /// The test passed, but only after one or more failed attempts.
+    Flaky,
+    /// The test was not run because the run stopped early.
+    NotRun,
+    /// The test failed, as it was expected to.
+    ExpectedFailure,
+    /// The test passed, but it was expected to fail.
+    UnexpectedPass
 }
 
 impl TestResult {
     pub fn is_success(&self) -> bool {
-        matches!(self, TestResult::Success)
+        matches!(self, TestResult::Success | TestResult::Flaky)
+    }
+
+    pub fn is_flaky(&self) -> bool {
+        matches!(self, TestResult::Flaky)
     }
 
     pub fn is_ignore(&self) -> bool {
         matches!(self, TestResult::Ignore)
     }
 
+    pub fn is_expected_failure(&self) -> bool {
+        matches!(self, TestResult::ExpectedFailure)
+    }
+
+    pub fn is_unexpected_pass(&self) -> bool {
+        matches!(self, TestResult::UnexpectedPass)
+    }
+
+    pub fn is_not_run(&self) -> bool {
+        matches!(self, TestResult::NotRun)
+    }
+
     pub fn is_failure(&self) -> bool {
         matches!(self, TestResult::Failure)
     }
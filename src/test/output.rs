@@ -1,11 +1,38 @@
+use core::fmt::Write;
 use heapless::{format, String};
-use crate::{debugcon_println, MAX_STRING_LENGTH};
+use crate::{debugcon_print, debugcon_println, MAX_STRING_LENGTH, alloc::HeapUsage, log::CapturedOutput, test::assertion::{Assertion, SoftFailures}};
+
+/// Optional fields attached to a test result record. Fields which are `None` are omitted.
+#[derive(Default)]
+pub struct Details<'a> {
+    /// The iteration index (starting at 1), if the test is repeated.
+    pub iteration: Option<usize>,
+    /// The attempt index (starting at 1), if the test has retries.
+    pub attempt: Option<usize>,
+    /// The reason a test was ignored (from `#[ignore = "reason"]` or `skip!`) or is expected to fail.
+    pub reason: Option<&'a str>,
+    /// The failed structured assertion (e.g. `ktest::assert_eq!`), if the test failed on one.
+    pub assertion: Option<&'a Assertion>,
+    /// The failed soft assertions (e.g. `ktest::check!`) of the test, if any.
+    pub soft_failures: Option<&'a SoftFailures>,
+    /// The output captured while the test ran (from `serial_print!` and `debugcon_print!`).
+    pub stdout: Option<&'a CapturedOutput>,
+    /// The number of frames the test left allocated from its `FrameArena`s, if any.
+    pub frame_leak: Option<usize>,
+    /// The heap allocations the test left live, if any.
+    pub heap_leak: Option<HeapUsage>
+}
 
 /// Writes a JSON object indicating the start of a test group with its name and test count.
-pub fn write_test_group(test_group: &str, test_count: usize) {
+/// If the tests are shuffled, the seed is included so the order can be replayed.
+pub fn write_test_group(test_group: &str, test_count: usize, seed: Option<u64>) {
     let use_kview = cfg!(feature = "kview");
-    let test_group_json: String<MAX_STRING_LENGTH> = format!(r#"{{ "test_group": "{}", "test_count": {}, "use_kview": {} }}"#, 
-        test_group, test_count, use_kview).unwrap();
+    let test_group_json: String<MAX_STRING_LENGTH> = match seed {
+        Some(seed) => format!(r#"{{ "test_group": "{}", "test_count": {}, "use_kview": {}, "seed": {} }}"#, 
+            test_group, test_count, use_kview, seed).unwrap(),
+        None => format!(r#"{{ "test_group": "{}", "test_count": {}, "use_kview": {} }}"#, 
+            test_group, test_count, use_kview).unwrap()
+    };
     let test_group_json = replace_heapless_string(&test_group_json, "\n", "").unwrap();
     let test_group_json = replace_heapless_string(&test_group_json, "\t", "").unwrap();
 
@@ -13,7 +40,7 @@ pub fn write_test_group(test_group: &str, test_count: usize) {
 }
 
 /// Writes a JSON object indicating the success of a test case, including its name and cycle count.
-pub fn write_test_success(test_name: &str, cycle_count: u64) {
+pub fn write_test_success(test_name: &str, cycle_count: u64, details: &Details) {
     let test_json: String<MAX_STRING_LENGTH> = format!(r#"
 {{
     "test": "{}",
@@ -23,11 +50,25 @@ pub fn write_test_success(test_name: &str, cycle_count: u64) {
     let test_json = replace_heapless_string(&test_json, "\n", "").unwrap();
     let test_json = replace_heapless_string(&test_json, "   ", "").unwrap();
 
-    debugcon_println!("{}", test_json);
+    print_record(&test_json, details);
+}
+
+/// Writes a JSON object indicating that a test case passed after one or more failed attempts.
+pub fn write_test_flaky(test_name: &str, cycle_count: u64, details: &Details) {
+    let test_json: String<MAX_STRING_LENGTH> = format!(r#"
+{{
+    "test": "{}",
+    "result": "flaky",
+    "cycle_count": {}
+}}"#, test_name, cycle_count).unwrap();
+    let test_json = replace_heapless_string(&test_json, "\n", "").unwrap();
+    let test_json = replace_heapless_string(&test_json, "   ", "").unwrap();
+
+    print_record(&test_json, details);
 }
 
 /// Writes a JSON object indicating the ignore of a test case, including its name and cycle count.
-pub fn write_test_ignore(test_name: &str) {
+pub fn write_test_ignore(test_name: &str, details: &Details) {
     let test_json: String<MAX_STRING_LENGTH> = format!(r#"
 {{
     "test": "{}",
@@ -37,27 +78,177 @@ pub fn write_test_ignore(test_name: &str) {
     let test_json = replace_heapless_string(&test_json, "\n", "").unwrap();
     let test_json = replace_heapless_string(&test_json, "   ", "").unwrap();
 
-    debugcon_println!("{}", test_json);
+    print_record(&test_json, details);
 }
 
-/// Writes a JSON object indicating the failure of a test case, including its name, location, and failure message.
-pub fn write_test_failure(test_name: &str, location: &str, message: &str) {
-    let location = replace_heapless_string(&String::<MAX_STRING_LENGTH>::try_from(location).unwrap(), "\\", "/").unwrap(); // prevents escape issues with heapless String
+/// Writes a JSON object indicating that a test case failed as expected, including its name,
+/// location, and failure message.
+pub fn write_test_xfail(test_name: &str, location: &str, message: &str, details: &Details) {
+    print_failure_record(test_name, "xfail", location, message, details);
+}
 
+/// Writes a JSON object indicating that a test case passed when it was expected to fail.
+pub fn write_test_xpass(test_name: &str, cycle_count: u64, details: &Details) {
     let test_json: String<MAX_STRING_LENGTH> = format!(r#"
 {{
     "test": "{}",
-    "result": "fail",
-    "cycle_count": 0,
-    "location": "{}",
-    "message": "{}"
-}}"#, test_name, location, message).unwrap();
+    "result": "xpass",
+    "cycle_count": {}
+}}"#, test_name, cycle_count).unwrap();
+    let test_json = replace_heapless_string(&test_json, "\n", "").unwrap();
+    let test_json = replace_heapless_string(&test_json, "   ", "").unwrap();
+
+    print_record(&test_json, details);
+}
+
+/// Writes a JSON object indicating that a test case was not run because the run stopped early.
+pub fn write_test_not_run(test_name: &str) {
+    let test_json: String<MAX_STRING_LENGTH> = format!(r#"
+{{
+    "test": "{}",
+    "result": "not_run",
+    "cycle_count": 0
+}}"#, test_name).unwrap();
     let test_json = replace_heapless_string(&test_json, "\n", "").unwrap();
     let test_json = replace_heapless_string(&test_json, "   ", "").unwrap();
 
     debugcon_println!("{}", test_json);
 }
 
+/// Writes a JSON object indicating the failure of a test case, including its name, location, and failure message.
+pub fn write_test_failure(test_name: &str, location: &str, message: &str, details: &Details) {
+    print_failure_record(test_name, "fail", location, message, details);
+}
+
+/// Writes a JSON object indicating a failed attempt of a test case which will be retried, including
+/// its name, location, and failure message.
+pub fn write_test_retry(test_name: &str, location: &str, message: &str, details: &Details) {
+    print_failure_record(test_name, "retry", location, message, details);
+}
+
+/// Writes a JSON object summarizing a repeated test, including its pass rate over all iterations.
+pub fn write_test_repeat_summary(test_name: &str, iterations: usize, passed: usize) {
+    let pass_rate = if iterations > 0 { passed as f64 * 100.0 / iterations as f64 } else { 0.0 };
+    let summary_json: String<MAX_STRING_LENGTH> = format!(r#"
+{{
+    "test_summary": "{}",
+    "iterations": {},
+    "passed": {},
+    "failed": {},
+    "pass_rate": {:.2}
+}}"#, test_name, iterations, passed, iterations - passed, pass_rate).unwrap();
+    let summary_json = replace_heapless_string(&summary_json, "\n", "").unwrap();
+    let summary_json = replace_heapless_string(&summary_json, "   ", "").unwrap();
+
+    debugcon_println!("{}", summary_json);
+}
+
+/// Helper function to print a JSON object with the optional fields in `details` added to the end.
+/// The fields are printed piece by piece, so they are not limited by the length of `json`.
+fn print_record(json: &str, details: &Details) {
+    let Some(object) = json.strip_suffix('}') else {
+        debugcon_println!("{}", json); // not a JSON object
+        return;
+    };
+
+    debugcon_print!("{}", object);
+    print_fields(details);
+}
+
+/// Helper function to print a JSON object for a failed test (or attempt), with its location and
+/// message, and the optional fields in `details` added to the end. This is reached from the panic
+/// handler, so every field is printed piece by piece instead of being formatted into a heapless
+/// String which a long message could overflow.
+fn print_failure_record(test_name: &str, result: &str, location: &str, message: &str, details: &Details) {
+    debugcon_print!(r#"{{ "test": "{}", "result": "{}", "cycle_count": 0"#, JsonEscaped(test_name), result);
+    debugcon_print!(r#", "location": "{}", "message": "{}""#, JsonLocation(location), JsonEscaped(message));
+    print_fields(details);
+}
+
+/// Helper function to print the optional fields in `details`, and the end of the JSON object.
+fn print_fields(details: &Details) {
+    if let Some(iteration) = details.iteration {
+        debugcon_print!(r#", "iteration": {}"#, iteration);
+    }
+    if let Some(attempt) = details.attempt {
+        debugcon_print!(r#", "attempt": {}"#, attempt);
+    }
+    if let Some(reason) = details.reason {
+        debugcon_print!(r#", "reason": "{}""#, JsonEscaped(reason));
+    }
+    if let Some(assertion) = details.assertion {
+        debugcon_print!(r#", "expression": "{}""#, JsonEscaped(&assertion.expression));
+        debugcon_print!(r#", "expected": "{}""#, JsonEscaped(&assertion.expected));
+        debugcon_print!(r#", "actual": "{}""#, JsonEscaped(&assertion.actual));
+        if let Some(offset) = assertion.offset {
+            debugcon_print!(r#", "offset": {}"#, offset);
+        }
+    }
+    if let Some(soft_failures) = details.soft_failures.filter(|soft_failures| !soft_failures.is_empty()) {
+        debugcon_print!(r#", "failures": ["#);
+        for (i, failure) in soft_failures.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            debugcon_print!(r#"{}{{"location": "{}", "message": "{}"}}"#, separator,
+                JsonEscaped(&failure.location), JsonEscaped(&failure.message));
+        }
+        debugcon_print!("]");
+        if soft_failures.dropped() > 0 {
+            debugcon_print!(r#", "dropped_failures": {}"#, soft_failures.dropped());
+        }
+    }
+    if let Some(frame_leak) = details.frame_leak {
+        debugcon_print!(r#", "frame_leak": {}"#, frame_leak);
+    }
+    if let Some(heap_leak) = details.heap_leak {
+        debugcon_print!(r#", "leaked_bytes": {}, "leaked_allocations": {}"#, heap_leak.bytes, heap_leak.allocations);
+    }
+    if let Some(capture) = details.stdout {
+        if let Some(output) = capture.output() {
+            debugcon_print!(r#", "stdout": "{}""#, JsonEscaped(output));
+        }
+        if capture.dropped() > 0 {
+            debugcon_print!(r#", "dropped_stdout": {}"#, capture.dropped());
+        }
+    }
+    debugcon_println!("}}");
+}
+
+/// Helper to write a string with JSON escapes for quotes, backslashes, and control characters
+struct JsonEscaped<'a>(&'a str);
+
+impl core::fmt::Display for JsonEscaped<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Helper to write a location with JSON escapes, with backslashes replaced by slashes (the same as
+/// in the locations of soft assertion failures)
+struct JsonLocation<'a>(&'a str);
+
+impl core::fmt::Display for JsonLocation<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, part) in self.0.split('\\').enumerate() {
+            if i > 0 {
+                f.write_char('/')?;
+            }
+            write!(f, "{}", JsonEscaped(part))?;
+        }
+        Ok(())
+    }
+}
+
 /// Helper function to replace all occurrences of a substring in a heapless String
 fn replace_heapless_string(
     original: &String<MAX_STRING_LENGTH>,
@@ -1,7 +1,8 @@
+use core::{arch::asm, fmt::Write, sync::atomic::{AtomicU64, Ordering}};
 use conquer_once::spin::OnceCell;
 use heapless::{format, String};
 use spin::RwLock;
-use crate::{MAX_STRING_LENGTH, args, qemu, serial_print, serial_println, test::{self, Ignore, ShouldPanic, TestCase, outcome::TestResult}};
+use crate::{MAX_STRING_LENGTH, alloc, args, cpu, log::{self, CapturedOutput}, memory, qemu, serial_print, serial_println, test::{self, Ignore, ShouldPanic, TestCase, assertion::{Assertion, SoftFailures}, options, order, outcome::TestResult, output::Details}};
 
 /// A static reference to the list of test functions to run. This is unsafe but only set 
 /// once at the start of runner. The static nature of the tests makes it impossible to use 
@@ -11,12 +12,35 @@ static mut TESTS: &'static [&'static dyn TestCase] = &[];
 /// The global test runner instance. This is initialized once at the start of runner.
 pub static TEST_RUNNER: OnceCell<KernelTestRunner> = OnceCell::uninit();
 
-/// Tracker for the curent test index (corresponding to the index in TESTS)
+/// Tracker for the curent test position (mapped to an index in TESTS by `order::test_index`)
 pub static CURRENT_TEST_INDEX: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
 
 /// Tracker for the current module name, to print headers when it changes
 pub static CURRENT_MODULE: OnceCell<RwLock<&'static str>> = OnceCell::new(RwLock::new(""));
 
+/// Tracker for the current case of a parameterized test (starting at 0)
+pub static CURRENT_CASE: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
+
+/// Tracker for the current iteration of a repeated test (starting at 0)
+pub static CURRENT_ITERATION: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
+
+/// Tracker for the number of passed iterations of the current test
+pub static PASSED_ITERATIONS: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
+
+/// Tracker for the current attempt of a test with retries (starting at 0)
+pub static CURRENT_ATTEMPT: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
+
+/// Tracker for the number of failed tests (or iterations) so far, for `max_failures`
+pub static FAILURE_COUNT: OnceCell<RwLock<usize>> = OnceCell::new(RwLock::new(0));
+
+/// Set once `before_tests` has run, so that resuming after a panic does not run it again
+static TESTS_STARTED: OnceCell<()> = OnceCell::uninit();
+
+/// The stack pointer of `runner` before it ran any test. The runner resumes from here after a
+/// test fails, so that the frames of the failed test (and of the panic handler) are dropped
+/// instead of being built upon; otherwise, every failure would cost its own stack space.
+static RUNNER_STACK: AtomicU64 = AtomicU64::new(0);
+
 /// A test runner that runs the given tests and exits QEMU after completion.
 /// 
 /// Output from this runner is formatted as line-delimited JSON and printed to the debug 
@@ -25,9 +49,41 @@ pub fn runner(tests: &'static [&'static dyn TestCase]) -> ! {
     unsafe { TESTS = tests; }
 
     TEST_RUNNER.get_or_init(|| KernelTestRunner::default());
+
+    let stack_pointer: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags)) };
+    RUNNER_STACK.store(stack_pointer, Ordering::SeqCst);
+
     TEST_RUNNER.get().unwrap().run_tests(0)
 }
 
+/// Runs the tests starting from the given index on the runner's original stack. This is used to
+/// continue after a test stops early (e.g. by panicking); everything on the stack below `runner`
+/// is abandoned, so each failure starts from the same depth.
+fn resume_tests(start_index: usize) -> ! {
+    let stack_pointer = RUNNER_STACK.load(Ordering::SeqCst);
+    if stack_pointer == 0 { // the runner was not started through `runner`
+        TEST_RUNNER.get().unwrap().run_tests(start_index);
+    }
+
+    // the stack is aligned to 16 bytes at the call, as the System V ABI requires
+    unsafe {
+        asm!(
+            "mov rsp, {stack_pointer}",
+            "call {run_tests}",
+            stack_pointer = in(reg) stack_pointer & !0xf,
+            run_tests = sym run_tests_from,
+            in("rdi") start_index,
+            options(noreturn)
+        )
+    }
+}
+
+/// Helper function for `resume_tests`, called on the runner's original stack
+extern "sysv64" fn run_tests_from(start_index: usize) -> ! {
+    TEST_RUNNER.get().unwrap().run_tests(start_index)
+}
+
 /// A trait defining the behavior of a test runner.
 pub trait TestRunner {
     /// Runs once before all tests.
@@ -45,6 +101,25 @@ pub trait TestRunner {
     /// Called when a test panics. This should print the panic information, mark the current
     /// test as failed, and continue with the next test (if possible).
     fn handle_panic(&self, info: &core::panic::PanicInfo) -> !;
+    /// Called when a test skips itself at runtime. This should mark the current test as ignored
+    /// with the given reason, and continue with the next test (if possible).
+    fn handle_skip(&self, reason: &str) -> !;
+    /// Called when a heap allocation fails. This should mark the current test as failed with the
+    /// requested layout, and continue with the next test (if possible).
+    #[cfg(feature = "allocator")]
+    fn handle_alloc_error(&self, layout: core::alloc::Layout) -> !;
+}
+
+/// Stops the current test and reports it as ignored with the given reason. This is used by the
+/// `skip!` macro, and is not considered part of the public API.
+#[doc(hidden)]
+pub fn skip(args: core::fmt::Arguments) -> ! {
+    use core::fmt::Write;
+
+    let mut reason = String::<MAX_STRING_LENGTH>::new();
+    let _ = reason.write_fmt(args); // a reason which is too long is truncated
+
+    TEST_RUNNER.get().unwrap().handle_skip(&reason)
 }
 
 /// A kernel test runner that runs all tests sequentially and exits QEMU after completion.
@@ -56,27 +131,80 @@ impl TestRunner for KernelTestRunner {
         let test_group = args::get_test_group().unwrap_or("default");
         let tests = unsafe { TESTS };
 
-        test::output::write_test_group(test_group, tests.len());
+        order::init(args::get_klib_config().shuffle);
+        options::init();
+        if let Some(seed) = order::seed() {
+            serial_println!("\nShuffling {} tests with seed: {}", tests.len(), seed);
+        }
+
+        let test_count = tests.iter().map(|&test| case_count(test)).sum();
+        test::output::write_test_group(test_group, test_count, order::seed());
     }
 
     fn run_tests(&self, start_index: usize) -> ! {
-        if start_index == 0 { // dont run before_tests if resuming
+        if !TESTS_STARTED.is_initialized() { // dont run before_tests if resuming
+            TESTS_STARTED.init_once(|| ());
             self.before_tests();
         }
         
         let tests = unsafe { TESTS };
-        for (i, &test) in tests.iter().enumerate().skip(start_index) {
-            let cycle_start = self.start_test();
+        for i in start_index..tests.len() {
+            let test = tests[order::test_index(i, tests.len())];
+            let iterations = options::get().iterations(test);
 
-            match test.ignore() {
-                Ignore::No => {
-                    test.run();
-                    self.complete_test(TestResult::Success, cycle_start);
+            // a resumed parameterized test continues from the case that panicked
+            while *CURRENT_CASE.get().unwrap().read() < case_count(test) {
+                // tests which require missing CPU features are ignored, with the feature as the reason
+                let missing_feature = match test.ignore() {
+                    Ignore::No => cpu::missing_feature(test.requires()),
+                    Ignore::Yes | Ignore::YesWithMessage => None
+                };
+                if let Some(feature) = missing_feature {
+                    let reason: String<MAX_STRING_LENGTH> = match cpu::has_feature(feature) {
+                        Some(_) => format!("missing CPU feature: {}", feature),
+                        None => format!("unknown CPU feature: {}", feature)
+                    }.unwrap();
+
+                    self.start_test();
+                    self.report_ignore(test, &reason);
                 }
-                Ignore::Yes => {
-                    self.complete_test(TestResult::Ignore, cycle_start);
+
+                // a resumed test continues from the iteration after the one that panicked
+                while missing_feature.is_none() && *CURRENT_ITERATION.get().unwrap().read() < iterations {
+                    let cycle_start = self.start_test();
+
+                    match test.ignore() {
+                        Ignore::No => {
+                            log::start_capture();
+                            test.run_case(*CURRENT_CASE.get().unwrap().read());
+                            alloc::finish_tracking();
+                            log::stop_capture();
+                            if !test::assertion::soft_failures().is_empty() {
+                                self.fail_soft_assertions();
+                            }
+                            if options::get().fail_on_leaks {
+                                self.fail_leaks();
+                            }
+                            let result = match test.xfail() {
+                                Some(_) => TestResult::UnexpectedPass,
+                                None => passed_result()
+                            };
+                            self.complete_test(result, cycle_start);
+                        }
+                        Ignore::Yes | Ignore::YesWithMessage => {
+                            self.complete_test(TestResult::Ignore, cycle_start);
+                            break; // ignored tests are only reported once
+                        }
+                    }
+
+                    *CURRENT_ITERATION.get().unwrap().write() += 1;
+                    *CURRENT_ATTEMPT.get().unwrap().write() = 0;
                 }
+                finish_iterations(test);
+
+                *CURRENT_CASE.get().unwrap().write() += 1;
             }
+            *CURRENT_CASE.get().unwrap().write() = 0;
 
             if !increment_test_index(i) {
                 break; // no more tests to run
@@ -90,25 +218,33 @@ impl TestRunner for KernelTestRunner {
     }
 
     fn start_test(&self) -> u64 {
-        // check if the current module has changed; if so, reassign it and print a header
         let current_test = self.current_test().unwrap();
-        
-        let module_path = current_test.modules().unwrap_or("unknown_module");
-        {
-            let mut current_module = CURRENT_MODULE.get().unwrap().write();
-            if *current_module != module_path {
-                *current_module = module_path;
-
-                let module_test_count = count_by_module(module_path);
-                let test_group = args::get_test_group().unwrap_or("default");
-                serial_println!("\n################################################################");
-                serial_println!("# Running {} {} tests for module: {}", module_test_count, test_group, module_path);
-                serial_println!("----------------------------------------------------------------");
-            }
-        } // scope will release the lock here
+        print_module_header(current_test);
 
         // print the test name with padding for aligned results
-        print_test_name(current_test.name(), 58);
+        let iterations = options::get().iterations(current_test);
+        let attempt = *CURRENT_ATTEMPT.get().unwrap().read();
+        let mut display_name = case_name(current_test);
+        if matches!(current_test.ignore(), Ignore::No) {
+            if iterations > 1 {
+                let iteration = *CURRENT_ITERATION.get().unwrap().read() + 1;
+                display_name = format!("{} ({}/{})", display_name, iteration, iterations).unwrap_or(display_name);
+            }
+            if attempt > 0 {
+                display_name = format!("{} [attempt {}/{}]", display_name, attempt + 1, current_test.retries() + 1).unwrap_or(display_name);
+            }
+        }
+        print_test_name(&display_name, 58);
+
+        // soft assertion failures, captured output and leaks only belong to the test that produced them
+        test::assertion::soft_failures().clear();
+        log::captured_output().clear();
+        memory::frame::reset_leaked_frames();
+        alloc::start_tracking();
+
+        // faults injected by the previous test must not fail this one
+        alloc::clear_faults();
+        memory::frame::clear_faults();
 
         // return the current cycle (for duration calculation later)
         read_current_cycle()
@@ -124,18 +260,58 @@ impl TestRunner for KernelTestRunner {
         match result {
             TestResult::Success => {
                 let current_test = self.current_test().unwrap();
-                let test_name: String<MAX_STRING_LENGTH> = format!("{}::{}", current_test.modules().unwrap(), current_test.name()).unwrap();
-                test::output::write_test_success(&test_name, cycle_count);
+                let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+                let capture = log::take_captured_output();
+                let details = Details { stdout: Some(&capture), ..current_details(current_test) };
+                test::output::write_test_success(&test_name, cycle_count, &details);
+                *PASSED_ITERATIONS.get().unwrap().write() += 1;
                 serial_println!("[pass]");
+                print_leaks();
+                print_captured_output(&capture, false);
+            }
+            TestResult::Flaky => {
+                let current_test = self.current_test().unwrap();
+                let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+                let capture = log::take_captured_output();
+                let details = Details { stdout: Some(&capture), ..current_details(current_test) };
+                test::output::write_test_flaky(&test_name, cycle_count, &details);
+                *PASSED_ITERATIONS.get().unwrap().write() += 1;
+                serial_println!("[flaky]");
+                print_leaks();
+                print_captured_output(&capture, false);
+            }
+            TestResult::UnexpectedPass => {
+                let current_test = self.current_test().unwrap();
+                let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+                let capture = log::take_captured_output();
+                let details = Details { reason: current_test.xfail(), stdout: Some(&capture), ..current_details(current_test) };
+                test::output::write_test_xpass(&test_name, cycle_count, &details);
+                serial_println!("[xpass]");
+                print_leaks();
+                print_captured_output(&capture, false);
             }
-            TestResult::Failure => {
-                // panic handler will print [fail] with details (and same for JSON output)
+            TestResult::Failure | TestResult::ExpectedFailure => {
+                // panic handler will print [fail] or [xfail] with details (and same for JSON output)
             }
             TestResult::Ignore => {
                 let current_test = self.current_test().unwrap();
-                let test_name: String<MAX_STRING_LENGTH> = format!("{}::{}", current_test.modules().unwrap(), current_test.name()).unwrap();
-                test::output::write_test_ignore(&test_name);
-                serial_println!("[ignore]");
+                let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+                match current_test.ignore_reason() {
+                    Some(reason) => {
+                        test::output::write_test_ignore(&test_name, &Details { reason: Some(reason), ..Details::default() });
+                        serial_println!("[ignore] ({})", reason);
+                    }
+                    None => {
+                        test::output::write_test_ignore(&test_name, &Details::default());
+                        serial_println!("[ignore]");
+                    }
+                }
+            }
+            TestResult::NotRun => {
+                let current_test = self.current_test().unwrap();
+                let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+                test::output::write_test_not_run(&test_name);
+                serial_println!("[not run]");
             }
         }
     }
@@ -143,41 +319,265 @@ impl TestRunner for KernelTestRunner {
     fn current_test(&self) -> Option<&'static dyn TestCase> {
         let current_index = *CURRENT_TEST_INDEX.get().unwrap().read();
         let tests = unsafe { TESTS };
-        tests.get(current_index).copied()
+        if current_index >= tests.len() {
+            return None;
+        }
+        tests.get(order::test_index(current_index, tests.len())).copied()
     }
 
     fn handle_panic(&self, info: &core::panic::PanicInfo) -> ! {
+        log::stop_capture(); // the runner's own output is never captured
         // finish the test output, replaces [pass] with panic details
-        let location = if let Some(location) = info.location() {
-            format!("{}:{}", location.file(), location.line()).unwrap()
-        } else {
-            String::<MAX_STRING_LENGTH>::try_from("unknown location").unwrap()
+        let mut location = String::<MAX_STRING_LENGTH>::new();
+        let _ = match info.location() { // a location which is too long is cut short
+            Some(location_info) => write!(location, "{}:{}", location_info.file(), location_info.line()),
+            None => write!(location, "unknown location")
         };
-        let message = info.message().as_str().unwrap_or("no message");
+        let mut message = String::<MAX_STRING_LENGTH>::new();
+        let _ = write!(message, "{}", info.message()); // a message which is too long is cut short
+        if message.is_empty() {
+            let _ = message.push_str("no message");
+        }
+        let assertion = test::assertion::take_last_assertion();
+
+        self.fail_current_test(&location, &message, assertion.as_ref(), true)
+    }
+
+    #[cfg(feature = "allocator")]
+    fn handle_alloc_error(&self, layout: core::alloc::Layout) -> ! {
+        log::stop_capture(); // the runner's own output is never captured
+
+        // the message is built on the stack, as the heap is out of memory
+        let usage = alloc::usage();
+        let mut message = String::<MAX_STRING_LENGTH>::new();
+        let _ = write!(message, "memory allocation of {} bytes (alignment {}) failed; heap usage: {} bytes in {} allocations",
+            layout.size(), layout.align(), usage.bytes, usage.allocations);
+        if let Some(heap_size) = memory::heap::size() {
+            let _ = write!(message, ", of a {} byte heap", heap_size);
+        }
+
+        // an allocation failure aborts the test the same as a panic (e.g. for `should_panic`)
+        self.fail_current_test("heap allocation", &message, None, true)
+    }
+
+    fn handle_skip(&self, reason: &str) -> ! {
+        log::stop_capture(); // the runner's own output is never captured
+        let current_test = self.current_test().unwrap();
+        test::fixture::teardown_all();
+
+        // finish the test output, replaces [pass] with the skip reason
+        self.report_ignore(current_test, reason);
+
+        // a skipped test is neither a pass nor a failure, and is not repeated or retried
+        *CURRENT_ATTEMPT.get().unwrap().write() = 0;
+        self.next_test(current_test)
+    }
+}
 
+impl KernelTestRunner {
+    /// Finishes the current test after it panicked (or returned with failed soft assertions),
+    /// reporting it according to whether it was expected to fail or panic, and continues with
+    /// the next attempt, iteration or test.
+    fn fail_current_test(&self, location: &str, message: &str, assertion: Option<&Assertion>, panicked: bool) -> ! {
         let current_test = self.current_test().unwrap();
-        let test_name: String<MAX_STRING_LENGTH> = format!("{}::{}", current_test.modules().unwrap(), current_test.name()).unwrap();
-
-        // handle according to whether the test was expected to panic
-        match current_test.should_panic() {
-            ShouldPanic::No => {
-                serial_println!("[fail] @ {}: {}", location, message); // expected that the line already has "test_name... "
-                test::output::write_test_failure(&test_name, location.as_str(), message);
-                self.complete_test(TestResult::Failure, u64::MAX);
+        let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(current_test);
+        let current_index = *CURRENT_TEST_INDEX.get().unwrap().read();
+        let attempt = *CURRENT_ATTEMPT.get().unwrap().read();
+
+        // the test body will never return, so its fixtures are torn down here
+        test::fixture::teardown_all();
+
+        // taken out of their locks before printing, as printing could panic again (which returns
+        // here through the panic handler), and the next test does not return here either
+        let soft_failures = test::assertion::take_soft_failures();
+        let checks = (!soft_failures.is_empty()).then_some(&soft_failures);
+        let capture = log::take_captured_output();
+
+        // a failed soft assertion fails the test, even if it was expected to panic
+        let expected_panic = panicked && matches!(current_test.should_panic(), ShouldPanic::Yes) && checks.is_none();
+
+        // `None` means that the test is retried
+        let result = if let Some(reason) = current_test.xfail() {
+            // a failure in a test which is expected to fail is not retried or counted as a failure
+            serial_println!("[xfail] @ {}: {}", location, message);
+            print_captured_output(&capture, false);
+            let details = Details { reason: Some(reason), assertion, soft_failures: checks, stdout: Some(&capture), ..current_details(current_test) };
+            test::output::write_test_xfail(&test_name, location, message, &details);
+            Some(TestResult::ExpectedFailure)
+        } else if expected_panic {
+            Some(passed_result())
+        } else if attempt < current_test.retries() {
+            // retry the same test (and iteration) if it has attempts left
+            serial_println!("[retry] @ {}: {}", location, message);
+            print_captured_output(&capture, true);
+            let details = Details { assertion, soft_failures: checks, stdout: Some(&capture), ..current_details(current_test) };
+            test::output::write_test_retry(&test_name, location, message, &details);
+            None
+        } else {
+            serial_println!("[fail] @ {}: {}", location, message); // expected that the line already has "test_name... "
+            if let Some(assertion) = assertion {
+                print_assertion(assertion);
             }
-            ShouldPanic::Yes => {
-                self.complete_test(TestResult::Success, u64::MAX);
+            if let Some(checks) = checks {
+                print_soft_failures(checks);
             }
+            print_captured_output(&capture, true);
+            let details = Details { assertion, soft_failures: checks, stdout: Some(&capture), ..current_details(current_test) };
+            test::output::write_test_failure(&test_name, location, message, &details);
+            Some(TestResult::Failure)
+        };
+        let Some(result) = result else {
+            *CURRENT_ATTEMPT.get().unwrap().write() += 1;
+            resume_tests(current_index);
+        };
+        let (expected_failure, failed) = (result.is_expected_failure(), result.is_failure());
+        self.complete_test(result, u64::MAX);
+        if expected_failure {
+            self.next_iteration(current_test, false);
         }
+        *CURRENT_ATTEMPT.get().unwrap().write() = 0;
 
-        // increment the test index to move to the next test (if possible)
+        // stop the run entirely if there have been too many failures
+        if failed {
+            let failures = {
+                let mut failure_count = FAILURE_COUNT.get().unwrap().write();
+                *failure_count += 1;
+                *failure_count
+            };
+            if options::get().max_failures.is_some_and(|max_failures| failures >= max_failures) {
+                finish_iterations(current_test);
+                self.stop_run(failures);
+            }
+        }
+
+        self.next_iteration(current_test, failed)
+    }
+
+    /// Fails the current test after it returned with failed soft assertions, reported at the
+    /// location of the first one.
+    fn fail_soft_assertions(&self) -> ! {
+        let (location, message) = {
+            let soft_failures = test::assertion::soft_failures();
+            let location: String<MAX_STRING_LENGTH> = soft_failures.iter().next()
+                .and_then(|failure| String::try_from(failure.location.as_str()).ok())
+                .unwrap_or_else(|| String::try_from("unknown location").unwrap());
+            let mut message = String::<MAX_STRING_LENGTH>::new();
+            let _ = match soft_failures.len() {
+                1 => write!(message, "1 check failed"),
+                count => write!(message, "{} checks failed", count)
+            };
+            (location, message)
+        }; // scope will release the lock here
+
+        self.fail_current_test(&location, &message, None, false)
+    }
+
+    /// Fails the current test after it returned, if it leaked resources (frames from a `FrameArena`,
+    /// or heap memory); otherwise, does nothing.
+    fn fail_leaks(&self) {
+        let leaked_frames = memory::frame::leaked_frames();
+        let heap_leak = alloc::leaked();
+        if leaked_frames == 0 && heap_leak.is_none() {
+            return;
+        }
+
+        let mut message = String::<MAX_STRING_LENGTH>::new();
+        if leaked_frames > 0 {
+            let _ = write!(message, "leaked {} frames", leaked_frames);
+        }
+        if let Some(heap_leak) = heap_leak {
+            let separator = if message.is_empty() { "" } else { ", " };
+            let _ = write!(message, "{}leaked {} bytes in {} allocations", separator, heap_leak.bytes, heap_leak.allocations);
+        }
+        self.fail_current_test("test teardown", &message, None, false)
+    }
+
+    /// Continues with the next iteration of the current test after a panic, if it has iterations
+    /// left, or otherwise with the next test.
+    fn next_iteration(&self, current_test: &dyn TestCase, failed: bool) -> ! {
+        let options = options::get();
+        let next_iteration = {
+            let mut current_iteration = CURRENT_ITERATION.get().unwrap().write();
+            *current_iteration += 1;
+            *current_iteration
+        };
+
+        // repeat the same test if it has iterations left
+        if next_iteration < options.iterations(current_test) && !(failed && options.repeat_until_failure) {
+            let current_index = *CURRENT_TEST_INDEX.get().unwrap().read();
+            resume_tests(current_index);
+        }
+
+        self.next_test(current_test)
+    }
+
+    /// Reports a test as ignored at runtime, with the given reason.
+    fn report_ignore(&self, test: &dyn TestCase, reason: &str) {
+        let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(test);
+        serial_println!("[ignore] ({})", reason);
+
+        let capture = log::take_captured_output();
+        print_captured_output(&capture, false);
+        let details = Details { reason: Some(reason), stdout: Some(&capture), ..current_details(test) };
+        test::output::write_test_ignore(&test_name, &details);
+    }
+
+    /// Finishes the current test and continues with the next test (and all thereafter).
+    fn next_test(&self, current_test: &dyn TestCase) -> ! {
+        finish_iterations(current_test);
+
+        // continue with the next case of a parameterized test (if any)
         let current_index = *CURRENT_TEST_INDEX.get().unwrap().read();
+        let next_case = {
+            let mut current_case = CURRENT_CASE.get().unwrap().write();
+            *current_case += 1;
+            *current_case
+        };
+        if next_case < case_count(current_test) {
+            resume_tests(current_index);
+        }
+        *CURRENT_CASE.get().unwrap().write() = 0;
+
+        // increment the test index to move to the next test (if possible)
         if !increment_test_index(current_index) {
             qemu::exit(qemu::ExitCode::Success); // no more tests to run
         }
 
         // continue with the next test (and all thereafter)
-        self.run_tests(current_index + 1) // continue with next test
+        resume_tests(current_index + 1) // continue with next test
+    }
+
+    /// Stops the run after too many failures, reporting every remaining test as not run and
+    /// exiting QEMU with the failure code.
+    fn stop_run(&self, failures: usize) -> ! {
+        serial_println!("\nStopping after {} failures (max_failures); remaining tests will not run", failures);
+
+        // the remaining cases of the current test are not run either
+        let mut current_test = self.current_test().unwrap();
+        let mut next_case = *CURRENT_CASE.get().unwrap().read() + 1;
+
+        let mut current_index = *CURRENT_TEST_INDEX.get().unwrap().read();
+        loop {
+            while next_case < case_count(current_test) {
+                *CURRENT_CASE.get().unwrap().write() = next_case;
+                print_module_header(current_test);
+                print_test_name(&case_name(current_test), 58);
+                self.complete_test(TestResult::NotRun, u64::MAX);
+                next_case += 1;
+            }
+            next_case = 0;
+
+            if !increment_test_index(current_index) {
+                break;
+            }
+            current_index += 1;
+            let Some(test) = self.current_test() else {
+                break; // no more tests
+            };
+            current_test = test;
+        }
+
+        qemu::exit(qemu::ExitCode::Failed)
     }
 }
 
@@ -199,12 +599,173 @@ fn increment_test_index(base: usize) -> bool {
     true
 }
 
+/// Helper function to build the optional record fields for the current iteration and attempt.
+fn current_details(test: &dyn TestCase) -> Details<'static> {
+    let iteration = if options::get().iterations(test) > 1 {
+        Some(*CURRENT_ITERATION.get().unwrap().read() + 1)
+    } else {
+        None
+    };
+    let attempt = if test.retries() > 0 {
+        Some(*CURRENT_ATTEMPT.get().unwrap().read() + 1)
+    } else {
+        None
+    };
+
+    let frame_leak = match memory::frame::leaked_frames() {
+        0 => None,
+        leaked_frames => Some(leaked_frames)
+    };
+
+    Details { iteration, attempt, frame_leak, heap_leak: alloc::leaked(), ..Details::default() }
+}
+
+/// Helper function to get the result of a passing test; a pass after failed attempts is flaky.
+fn passed_result() -> TestResult {
+    if *CURRENT_ATTEMPT.get().unwrap().read() > 0 {
+        TestResult::Flaky
+    } else {
+        TestResult::Success
+    }
+}
+
+/// Helper function to report the pass rate of a repeated test (if it was repeated) and
+/// reset the iteration trackers for the next test.
+fn finish_iterations(test: &dyn TestCase) {
+    let mut current_iteration = CURRENT_ITERATION.get().unwrap().write();
+    let mut passed = PASSED_ITERATIONS.get().unwrap().write();
+
+    if *current_iteration > 1 {
+        let test_name: String<MAX_STRING_LENGTH> = qualified_case_name(test);
+        let pass_rate = *passed as f64 * 100.0 / *current_iteration as f64;
+        serial_println!("{}: {}/{} iterations passed ({:.2}%)", case_name(test), *passed, *current_iteration, pass_rate);
+        test::output::write_test_repeat_summary(&test_name, *current_iteration, *passed);
+    }
+
+    *current_iteration = 0;
+    *passed = 0;
+}
+
+/// Helper function to print a header for the module of the given test, if it differs from the
+/// module of the previous test.
+fn print_module_header(test: &dyn TestCase) {
+    let module_path = test.modules().unwrap_or("unknown_module");
+    let mut current_module = CURRENT_MODULE.get().unwrap().write();
+    if *current_module == module_path {
+        return;
+    }
+    *current_module = module_path;
+
+    let module_test_count = count_by_module(module_path);
+    let test_group = args::get_test_group().unwrap_or("default");
+    serial_println!("\n################################################################");
+    serial_println!("# Running {} {} tests for module: {}", module_test_count, test_group, module_path);
+    serial_println!("----------------------------------------------------------------");
+}
+
 /// Helper function to count the number of tests in a given module.
 fn count_by_module(module_name: &str) -> usize {
     let tests = unsafe { TESTS };
     tests.iter()
         .filter(|&&test| test.modules().unwrap_or("") == module_name)
-        .count()
+        .map(|&test| case_count(test))
+        .sum()
+}
+
+/// Helper function to count the cases of a test; tests which are not parameterized have one.
+fn case_count(test: &dyn TestCase) -> usize {
+    test.cases().unwrap_or(1)
+}
+
+/// Helper function to get the name of a test, with the current case index for parameterized
+/// tests (e.g. `my_test[0]`).
+/// A name which is too long is cut short, as this is also used from the panic handler.
+fn case_name(test: &dyn TestCase) -> String<MAX_STRING_LENGTH> {
+    let mut name = String::new();
+    let _ = match test.cases() {
+        Some(_) => write!(name, "{}[{}]", test.name(), *CURRENT_CASE.get().unwrap().read()),
+        None => write!(name, "{}", test.name())
+    };
+    name
+}
+
+/// Helper function to get the qualified name of a test (e.g. `my_crate::tests::my_test[0]`).
+/// A name which is too long is cut short, as this is also used from the panic handler.
+fn qualified_case_name(test: &dyn TestCase) -> String<MAX_STRING_LENGTH> {
+    let mut name = String::new();
+    let _ = write!(name, "{}::{}", test.modules().unwrap_or("unknown_module"), case_name(test));
+    name
+}
+
+/// Helper to print the values of a failed structured assertion as an aligned diff, with a marker
+/// under the first difference when both values fit on one line (or a hexdump, for byte comparisons)
+fn print_assertion(assertion: &Assertion) {
+    serial_println!("    expression: {}", assertion.expression);
+    serial_println!("      expected: {}", assertion.expected);
+    serial_println!("        actual: {}", assertion.actual);
+
+    if let Some(offset) = assertion.offset {
+        serial_println!("    first difference at offset {:#x}:", offset);
+        for line in assertion.hexdump.lines() {
+            serial_println!("    {}", line);
+        }
+        return;
+    }
+
+    let single_line = !assertion.expected.contains('\n') && !assertion.actual.contains('\n');
+    let first_difference = assertion.expected.chars()
+        .zip(assertion.actual.chars())
+        .position(|(expected, actual)| expected != actual)
+        .or_else(|| {
+            let (expected, actual) = (assertion.expected.chars().count(), assertion.actual.chars().count());
+            (expected != actual).then(|| expected.min(actual))
+        });
+    if let (true, Some(offset)) = (single_line, first_difference) {
+        serial_print!("                ");
+        for _ in 0..offset {
+            serial_print!(" ");
+        }
+        serial_println!("^ first difference at offset {}", offset);
+    }
+}
+
+/// Helper to print each failed soft assertion of a test, below the failure line
+fn print_soft_failures(soft_failures: &SoftFailures) {
+    for failure in soft_failures.iter() {
+        serial_println!("    check failed @ {}: {}", failure.location, failure.message);
+    }
+    if soft_failures.dropped() > 0 {
+        serial_println!("    ... and {} more", soft_failures.dropped());
+    }
+}
+
+/// Helper to print a warning below the result line of a test which leaked resources (frames or heap memory)
+fn print_leaks() {
+    let leaked_frames = memory::frame::leaked_frames();
+    if leaked_frames > 0 {
+        serial_println!("    warning: frame_leak: {} frames were still allocated at the end of the test (quarantined)", leaked_frames);
+    }
+    if let Some(heap_leak) = alloc::leaked() {
+        serial_println!("    warning: heap_leak: {} bytes in {} allocations were still live at the end of the test", heap_leak.bytes, heap_leak.allocations);
+    }
+}
+
+/// Helper to print the output captured from a test, below its result line. Output is printed
+/// for failed tests, or for every test if the `verbose` option is set.
+fn print_captured_output(capture: &CapturedOutput, failed: bool) {
+    if !failed && !options::get().verbose {
+        return;
+    }
+
+    if let Some(output) = capture.output() {
+        serial_println!("    captured output:");
+        for line in output.lines() {
+            serial_println!("    | {}", line);
+        }
+    }
+    if capture.dropped() > 0 {
+        serial_println!("    ... and {} more bytes", capture.dropped());
+    }
 }
 
 /// Helper to write function names with padding for aligned results